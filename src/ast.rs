@@ -1,11 +1,170 @@
+use std::error;
 use std::fmt;
 use std::option;
 use std::result;
 use tokenizer;
+use tokenizer::Span;
+
+/// Everything that can go wrong while turning a token stream into an ast.
+/// `index` is the position of the offending token in the token stream and
+/// `span` is where that token sits in the source, when it is known.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseError {
+    EmptyInput,
+    EndOfStream {
+        index: usize,
+        span: Span,
+    },
+    UnexpectedClose {
+        index: usize,
+        span: Span,
+    },
+    /// `index` points at the opening brace that was never closed
+    UnterminatedList {
+        index: usize,
+        span: Span,
+    },
+    /// `span` points at the opening quote of the string
+    UnterminatedString {
+        index: usize,
+        span: Span,
+    },
+    InvalidEscape {
+        index: usize,
+        span: Span,
+    },
+    /// `span` points at the `#|` that was never closed
+    UnterminatedComment {
+        index: usize,
+        span: Span,
+    },
+    /// `span` points at the brace or prefix that went past `max_depth()`
+    TooDeep {
+        index: usize,
+        span: Span,
+    },
+}
+
+impl ParseError {
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            ParseError::EmptyInput => None,
+            ParseError::EndOfStream { span, .. }
+            | ParseError::UnexpectedClose { span, .. }
+            | ParseError::UnterminatedList { span, .. }
+            | ParseError::UnterminatedString { span, .. }
+            | ParseError::InvalidEscape { span, .. }
+            | ParseError::UnterminatedComment { span, .. }
+            | ParseError::TooDeep { span, .. } => Some(span).filter(Span::is_known),
+        }
+    }
+}
+
+// describes where an error happened, falling back to the token index when
+// the tokens were parsed without spans
+fn location(index: usize, span: &Span) -> String {
+    if span.is_known() {
+        format!("line {}, column {}", span.line, span.column)
+    } else {
+        format!("token {}", index)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::EmptyInput => write!(f, "token stream is empty"),
+            ParseError::EndOfStream { index, ref span } => {
+                write!(f, "reached end of stream at {}", location(index, span))
+            }
+            ParseError::UnexpectedClose { index, ref span } => write!(
+                f,
+                "found a closing brace without an opening brace at {}",
+                location(index, span)
+            ),
+            ParseError::UnterminatedList { index, ref span } => write!(
+                f,
+                "reached end of stream before finding a closing parenthesis for the brace at {}",
+                location(index, span)
+            ),
+            ParseError::UnterminatedString { index, ref span } => write!(
+                f,
+                "reached end of input inside the string starting at {}",
+                location(index, span)
+            ),
+            ParseError::InvalidEscape { index, ref span } => write!(
+                f,
+                "invalid escape sequence in string at {}",
+                location(index, span)
+            ),
+            ParseError::UnterminatedComment { index, ref span } => write!(
+                f,
+                "reached end of input inside the block comment starting at {}",
+                location(index, span)
+            ),
+            ParseError::TooDeep { index, ref span } => write!(
+                f,
+                "forms nested more than {} deep at {}",
+                max_depth(),
+                location(index, span)
+            ),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+depth_limit! {
+    /// How deeply lists and quote prefixes may nest when no other limit has
+    /// been set. The parser itself keeps its work on the heap, but the
+    /// finished tree is cloned, compared, printed and dropped recursively.
+    default = 1000
+}
+
+/// Source locations for a parsed form, shaped like the `SExpType` they were
+/// read with: an `Exp` of n items has n children, everything else has none.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SpanTree {
+    pub span: Span,
+    pub children: Vec<SpanTree>,
+}
+
+/// A parsed form together with where each of its parts came from.
+/// `exp` is the plain view that eval works on.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SpannedExp {
+    pub exp: SExpType,
+    pub spans: SpanTree,
+}
+
+impl SpannedExp {
+    /// Where the first part of this form that equals `target` came from,
+    /// looking at the form itself before its items, left to right.
+    pub fn span_of(&self, target: &SExpType) -> Option<Span> {
+        let mut pending = vec![(&self.exp, &self.spans)];
+        while let Some((exp, spans)) = pending.pop() {
+            if exp == target {
+                return Some(spans.span).filter(Span::is_known);
+            }
+            if let SExpType::Exp(ref items) = *exp {
+                pending.extend(items.iter().zip(spans.children.iter()).rev());
+            }
+        }
+        None
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum SExpType {
     Identifier(String),
+    /// An identifier that a `syntax-rules` template put into the code, the
+    /// number marks the expansion it came from. It binds a name of its own,
+    /// so it neither captures nor is captured by a user's identifier that is
+    /// written the same way. One the expansion doesn't bind means what it
+    /// does in the global scope, where the macro was defined.
+    Renamed(String, usize),
     Number(i64),
+    Str(String),
     Exp(Vec<SExpType>),
 }
 
@@ -23,16 +182,29 @@ impl SExpType {
         }
     }
 
+    /// True for plain and renamed identifiers alike.
     pub fn is_identifier(&self) -> bool {
         match *self {
-            SExpType::Identifier(_) => true,
+            SExpType::Identifier(_) | SExpType::Renamed(..) => true,
             _ => false,
         }
     }
 
+    /// The name of an identifier as it was written, without any renaming.
     pub fn get_identifier_name(&self) -> Option<String> {
+        match *self {
+            SExpType::Identifier(ref name) | SExpType::Renamed(ref name, _) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// The name an identifier binds and is looked up by. A renamed
+    /// identifier's includes its mark, and a space so no source text can
+    /// spell it.
+    pub fn binding_name(&self) -> Option<String> {
         match *self {
             SExpType::Identifier(ref name) => Some(name.clone()),
+            SExpType::Renamed(ref name, mark) => Some(format!("{} #{}", name, mark)),
             _ => None,
         }
     }
@@ -47,7 +219,9 @@ impl SExpType {
 impl fmt::Display for SExpType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            SExpType::Identifier(ref s) => write!(f, "{}", s.to_string()),
+            SExpType::Identifier(ref s) | SExpType::Renamed(ref s, _) => {
+                write!(f, "{}", s.to_string())
+            }
             SExpType::Exp(ref v) => {
                 write!(f, "{}", "(");
                 for (_, item) in v.iter().enumerate() {
@@ -56,100 +230,360 @@ impl fmt::Display for SExpType {
                 write!(f, ")")
             }
             SExpType::Number(no) => write!(f, "{}", no.to_string()),
+            SExpType::Str(ref s) => write!(f, "{}", quote_string(s)),
         }
     }
 }
 
-pub fn is_identifier(sexp: &SExpType) -> bool {
-    match sexp {
-        SExpType::Identifier(_) => true,
-        _ => false,
+/// Renders `s` as a double quoted literal that reads back as the same string.
+pub fn quote_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
     }
+    quoted.push('"');
+    quoted
+}
+
+pub fn is_identifier(sexp: &SExpType) -> bool {
+    sexp.is_identifier()
 }
 
-pub fn stream_to_ast(tokenv: &Vec<tokenizer::TokenType>) -> Result<SExpType, &'static str> {
+pub fn stream_to_ast(tokenv: &Vec<tokenizer::TokenType>) -> Result<SExpType, ParseError> {
     let mut index: usize = 0;
     make_ast(tokenv, &mut index)
 }
 
+/// Parses every top-level form in the token stream, in source order.
+pub fn parse_program(tokenv: &Vec<tokenizer::TokenType>) -> Result<Vec<SExpType>, ParseError> {
+    program_iter(tokenv).collect()
+}
+
+/// Tokenizes and parses a whole source string, keeping the span of every
+/// form so errors can point back into `source`.
+pub fn parse_spanned_program(source: &str) -> Result<Vec<SpannedExp>, ParseError> {
+    let (tokens, spans) = tokenizer::tokenize(source)?;
+    spanned_program_iter(&tokens, &spans).collect()
+}
+
+/// Returns an iterator that parses one top-level form at a time, so a
+/// caller can eval each form before the rest of the stream is parsed.
+pub fn program_iter(tokenv: &Vec<tokenizer::TokenType>) -> ProgramIter<'_> {
+    ProgramIter {
+        inner: spanned_program_iter(tokenv, &[]),
+    }
+}
+
+/// Like `program_iter`, but yields the spans of each form as well. `spans`
+/// is the span list that `tokenizer::tokenize` returned with `tokenv`.
+pub fn spanned_program_iter<'a>(
+    tokenv: &'a [tokenizer::TokenType],
+    spans: &'a [Span],
+) -> SpannedProgramIter<'a> {
+    SpannedProgramIter {
+        tokens: tokenv,
+        spans: spans,
+        index: 0,
+        failed: false,
+    }
+}
+
+pub struct ProgramIter<'a> {
+    inner: SpannedProgramIter<'a>,
+}
+
+impl<'a> Iterator for ProgramIter<'a> {
+    type Item = Result<SExpType, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|form| form.map(|spanned| spanned.exp))
+    }
+}
+
+pub struct SpannedProgramIter<'a> {
+    tokens: &'a [tokenizer::TokenType],
+    spans: &'a [Span],
+    index: usize,
+    failed: bool,
+}
+
+impl<'a> Iterator for SpannedProgramIter<'a> {
+    type Item = Result<SpannedExp, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Err(e) = skip_datum_comments(self.tokens, self.spans, &mut self.index) {
+            self.failed = true;
+            return Some(Err(e));
+        }
+        if self.index >= self.tokens.len() {
+            return None;
+        }
+        let form = make_spanned_ast(self.tokens, self.spans, &mut self.index);
+        // a form that failed to parse leaves the index in an unknown place,
+        // so stop instead of yielding garbage for the rest of the stream
+        self.failed = form.is_err();
+        Some(form)
+    }
+}
+
+/// Parses a single form starting at `sindex` and leaves `sindex` pointing
+/// at the first token after it.
 pub fn make_ast(
     tokenv: &Vec<tokenizer::TokenType>,
     sindex: &mut usize,
-) -> Result<SExpType, &'static str> {
-    if tokenv.len() == 0 {
-        Err("token stream is empty")
-    } else {
-        let mut parsing_sexp = false;
-        let mut exp_parsed = false;
-        let mut found_exp = false;
-        let mut identifier_only = Ok(SExpType::Identifier("".to_string()));
-        let mut exp_vec = Vec::new();
-        if *sindex == tokenv.len() {
-            Err("reached end of stream")
-        } else {
-            while *sindex < tokenv.len() {
-                match &tokenv[*sindex] {
-                    &tokenizer::TokenType::o_brace => {
-                        if !parsing_sexp {
-                            parsing_sexp = true;
-                            found_exp = true;
-                        } else {
-                            let m = make_ast(tokenv, sindex);
-                            match m {
-                                Ok(sub_exp) => {
-                                    exp_vec.push(sub_exp);
-                                }
-                                Err(s) => {
-                                    return Err(s);
-                                }
-                            }
-                        }
-                    }
-                    &tokenizer::TokenType::c_brace => {
-                        if !parsing_sexp {
-                            panic!(
-                                "found a closing brace without an opening brace at index {}",
-                                *sindex
-                            )
-                        }
-                        if exp_vec.is_empty() {
-                            return Err("found an empty expression");
-                        } else {
-                            exp_parsed = true;
-                            break;
-                        }
-                    }
+) -> Result<SExpType, ParseError> {
+    make_spanned_ast(tokenv, &[], sindex).map(|spanned| spanned.exp)
+}
 
-                    &tokenizer::TokenType::Identifier(ref s) => {
-                        // instead of pushing out an identifier here, check if this is a number
-                        // or a string and then push the right type into the ast
-                        let copy_of_s = s.clone();
-                        let maybeNumber = copy_of_s.parse::<i64>();
-                        if maybeNumber.is_ok() {
-                            if parsing_sexp {
-                                exp_vec.push(SExpType::Number(maybeNumber.unwrap()))
-                            } else {
-                                identifier_only = Ok(SExpType::Number(maybeNumber.unwrap()))
-                            }
-                        } else {
-                            if parsing_sexp {
-                                exp_vec.push(SExpType::Identifier(s.clone()))
-                            } else {
-                                identifier_only = Ok(SExpType::Identifier(s.clone()))
-                            }
-                        }
-                    }
+// the span of the token at `index`, or an empty span just past the last
+// token when the stream ran out
+fn span_at(spans: &[Span], index: usize) -> Span {
+    match spans.get(index) {
+        Some(span) => *span,
+        None => match spans.last() {
+            Some(last) => Span {
+                start: last.end,
+                end: last.end,
+                line: last.line,
+                column: last.column + (last.end - last.start),
+            },
+            None => Span::default(),
+        },
+    }
+}
+
+// consumes any `#;` tokens at `sindex` together with the form each of them
+// comments out
+fn skip_datum_comments(
+    tokenv: &[tokenizer::TokenType],
+    spans: &[Span],
+    sindex: &mut usize,
+) -> Result<(), ParseError> {
+    while *sindex < tokenv.len() && tokenv[*sindex] == tokenizer::TokenType::DatumComment {
+        *sindex += 1;
+        make_spanned_ast(tokenv, spans, sindex)?;
+    }
+    Ok(())
+}
+
+// a form the parser has started but not finished, kept on an explicit stack
+// so nesting depth costs heap rather than native stack
+enum OpenForm {
+    /// a `(` whose items so far are `items`, with their spans in `spans`
+    List {
+        index: usize,
+        span: Span,
+        items: Vec<SExpType>,
+        spans: Vec<SpanTree>,
+    },
+    /// `'`, `` ` ``, `,` or `,@` waiting for the form it wraps
+    Prefix { name: &'static str, span: Span },
+    /// `#;` waiting for the form it drops
+    Comment,
+}
+
+/// `make_ast` over a token stream that has spans. Passing an empty `spans`
+/// slice parses the same way but leaves every span unknown. Forms nested
+/// deeper than `max_depth()` are rejected with `ParseError::TooDeep`.
+pub fn make_spanned_ast(
+    tokenv: &[tokenizer::TokenType],
+    spans: &[Span],
+    sindex: &mut usize,
+) -> Result<SpannedExp, ParseError> {
+    if tokenv.len() == 0 {
+        return Err(ParseError::EmptyInput);
+    }
+    let max_depth = max_depth();
+    let mut open: Vec<OpenForm> = Vec::new();
+    let mut depth = 0;
+    loop {
+        if *sindex >= tokenv.len() {
+            return Err(match open.pop() {
+                Some(OpenForm::List { index, span, .. }) => ParseError::UnterminatedList {
+                    index: index,
+                    span: span,
+                },
+                _ => ParseError::EndOfStream {
+                    index: *sindex,
+                    span: span_at(spans, *sindex),
+                },
+            });
+        }
+        let start_span = span_at(spans, *sindex);
+        let mut form = match &tokenv[*sindex] {
+            &tokenizer::TokenType::o_brace
+            | &tokenizer::TokenType::Quote
+            | &tokenizer::TokenType::Quasiquote
+            | &tokenizer::TokenType::Unquote
+            | &tokenizer::TokenType::UnquoteSplicing => {
+                if depth == max_depth {
+                    return Err(ParseError::TooDeep {
+                        index: *sindex,
+                        span: start_span,
+                    });
                 }
+                depth += 1;
+                open.push(match tokenv[*sindex] {
+                    tokenizer::TokenType::o_brace => OpenForm::List {
+                        index: *sindex,
+                        span: start_span,
+                        items: Vec::new(),
+                        spans: Vec::new(),
+                    },
+                    tokenizer::TokenType::Quote => OpenForm::Prefix {
+                        name: "quote",
+                        span: start_span,
+                    },
+                    tokenizer::TokenType::Quasiquote => OpenForm::Prefix {
+                        name: "quasiquote",
+                        span: start_span,
+                    },
+                    tokenizer::TokenType::Unquote => OpenForm::Prefix {
+                        name: "unquote",
+                        span: start_span,
+                    },
+                    _ => OpenForm::Prefix {
+                        name: "unquote-splicing",
+                        span: start_span,
+                    },
+                });
                 *sindex += 1;
+                continue;
             }
-            if found_exp && !exp_parsed {
-                panic!("reached end of stream before finding a closing parenthesis");
+            &tokenizer::TokenType::DatumComment => {
+                open.push(OpenForm::Comment);
+                *sindex += 1;
+                continue;
+            }
+            &tokenizer::TokenType::c_brace => match open.pop() {
+                Some(OpenForm::List {
+                    span,
+                    items,
+                    spans: span_vec,
+                    ..
+                }) => {
+                    depth -= 1;
+                    SpannedExp {
+                        exp: SExpType::Exp(items),
+                        spans: SpanTree {
+                            span: span.to(&start_span),
+                            children: span_vec,
+                        },
+                    }
+                }
+                _ => {
+                    return Err(ParseError::UnexpectedClose {
+                        index: *sindex,
+                        span: start_span,
+                    })
+                }
+            },
+            &tokenizer::TokenType::Str(ref s) => SpannedExp {
+                exp: SExpType::Str(s.clone()),
+                spans: SpanTree {
+                    span: start_span,
+                    children: Vec::new(),
+                },
+            },
+            &tokenizer::TokenType::Identifier(ref s) => {
+                // instead of pushing out an identifier here, check if this is a number
+                // or a string and then push the right type into the ast
+                let exp = match s.parse::<i64>() {
+                    Ok(number) => SExpType::Number(number),
+                    Err(_) => SExpType::Identifier(s.clone()),
+                };
+                SpannedExp {
+                    exp: exp,
+                    spans: SpanTree {
+                        span: start_span,
+                        children: Vec::new(),
+                    },
+                }
             }
-            if parsing_sexp {
-                Ok(SExpType::Exp(exp_vec))
-            } else {
-                identifier_only
+        };
+        *sindex += 1;
+        // hand the finished form to whatever is waiting for it, closing
+        // prefixes as we go, until a list takes it or nothing is left open
+        loop {
+            match open.pop() {
+                None => return Ok(form),
+                Some(OpenForm::Comment) => break,
+                Some(OpenForm::Prefix { name, span }) => {
+                    depth -= 1;
+                    // 'x reads exactly like (quote x)
+                    form = SpannedExp {
+                        exp: SExpType::Exp(vec![SExpType::Identifier(name.to_string()), form.exp]),
+                        spans: SpanTree {
+                            span: span.to(&form.spans.span),
+                            children: vec![
+                                SpanTree {
+                                    span: span,
+                                    children: Vec::new(),
+                                },
+                                form.spans,
+                            ],
+                        },
+                    };
+                }
+                Some(OpenForm::List {
+                    index,
+                    span,
+                    mut items,
+                    spans: mut span_vec,
+                }) => {
+                    items.push(form.exp);
+                    span_vec.push(form.spans);
+                    open.push(OpenForm::List {
+                        index: index,
+                        span: span,
+                        items: items,
+                        spans: span_vec,
+                    });
+                    break;
+                }
             }
         }
     }
 }
+
+/// Formats `message` as a `name:line:col` diagnostic followed by the line of
+/// `source` that `span` points into, with carets under the spanned text.
+pub fn describe_at(name: &str, source: &str, span: &Span, message: &str) -> String {
+    if !span.is_known() {
+        return format!("{}: {}", name, message);
+    }
+    let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[span.start..]
+        .find('\n')
+        .map_or(source.len(), |i| span.start + i);
+    let line_text = &source[line_start..line_end];
+    let underline_end = if span.end > line_end {
+        line_end
+    } else {
+        span.end
+    };
+    let carets = source[span.start..underline_end].chars().count().max(1);
+    format!(
+        "{}:{}:{}: {}\n{}\n{}{}",
+        name,
+        span.line,
+        span.column,
+        message,
+        line_text,
+        " ".repeat(span.column - 1),
+        "^".repeat(carets)
+    )
+}
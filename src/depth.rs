@@ -0,0 +1,29 @@
+/// Defines the per-thread nesting limit of the module it is used in:
+/// `DEFAULT_MAX_DEPTH`, documented by the doc comments passed in, and the
+/// `max_depth` and `set_max_depth` functions that read and change it. Every
+/// level of nesting costs native stack, so the defaults are sized for the
+/// 8MB stack of a program's main thread. A thread with a smaller stack should
+/// lower its limits, and one that raises them needs a bigger stack to match,
+/// the way main.rs runs the interpreter.
+macro_rules! depth_limit {
+    ($(#[$doc:meta])* default = $default:expr) => {
+        $(#[$doc])*
+        pub const DEFAULT_MAX_DEPTH: usize = $default;
+
+        thread_local! {
+            static MAX_DEPTH: ::std::cell::Cell<usize> = ::std::cell::Cell::new(DEFAULT_MAX_DEPTH);
+        }
+
+        /// The nesting limit on this thread, `DEFAULT_MAX_DEPTH` unless
+        /// `set_max_depth` changed it.
+        pub fn max_depth() -> usize {
+            MAX_DEPTH.with(|depth| depth.get())
+        }
+
+        /// Sets the nesting limit for this thread and returns the old one.
+        /// Raise it only along with the stack size of the thread.
+        pub fn set_max_depth(depth: usize) -> usize {
+            MAX_DEPTH.with(|limit| limit.replace(depth))
+        }
+    };
+}
@@ -1,26 +1,390 @@
 use ast;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::error;
 use std::fmt;
+use std::mem;
+use std::ptr;
 use std::rc::Rc;
+use std::vec;
+use syntax_rules::SyntaxRules;
 
+/// What kind of thing went wrong in eval, for callers that want to react to
+/// an error without reading its message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// a variable that isn't bound in any enclosing scope
+    Unbound,
+    /// a function or builtin called with the wrong number of arguments
+    Arity,
+    /// a value of the wrong type, like `(car 1)` or `(+ 1 "a")`
+    Type,
+    /// a special form written the wrong way, like `(quote)`
+    Syntax,
+    /// integer overflow or division by zero
+    Arithmetic,
+    /// a `cond` none of whose clauses matched
+    NoMatch,
+    /// evaluation nested deeper than `max_depth()`
+    Depth,
+    /// an error returned by a builtin function
+    Builtin,
+}
+
+/// A Lisp function call that was in progress when an error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// the name the call site used, `lambda` when the function came from
+    /// anything other than a bare name
+    pub function: String,
+    pub args: Vec<Rc<IType>>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}", self.function)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Everything that can go wrong in eval: what kind of error it was, a
+/// message naming the offending values, the innermost expression whose
+/// evaluation failed and the calls that were in progress, innermost first.
+/// A call in tail position takes its caller's place, so of a chain of tail
+/// calls only the last one shows up in the backtrace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    // boxed so that results stay small, eval recurses on the native stack
+    // with plenty of them in every frame
+    details: Box<ErrorDetails>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ErrorDetails {
+    kind: ErrorKind,
+    message: String,
+    expr: Option<ast::SExpType>,
+    backtrace: Vec<Frame>,
+}
+
+impl EvalError {
+    pub fn new<S: Into<String>>(kind: ErrorKind, message: S) -> EvalError {
+        EvalError {
+            details: Box::new(ErrorDetails {
+                kind: kind,
+                message: message.into(),
+                expr: None,
+                backtrace: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.details.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.details.message
+    }
+
+    pub fn expr(&self) -> Option<&ast::SExpType> {
+        self.details.expr.as_ref()
+    }
+
+    pub fn backtrace(&self) -> &[Frame] {
+        &self.details.backtrace
+    }
+
+    // records `exp` as the failing expression, unless an inner one already is
+    fn at(mut self, exp: &ast::SExpType) -> EvalError {
+        if self.details.expr.is_none() {
+            self.details.expr = Some(exp.clone());
+        }
+        self
+    }
+
+    // records the call the error is passing out of
+    fn called_from(mut self, frame: Option<Frame>) -> EvalError {
+        self.details.backtrace.extend(frame);
+        self
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details.message)
+    }
+}
+
+impl error::Error for EvalError {}
+
+/// How many arguments a function takes. `max` is `None` when any number of
+/// arguments from `min` up is fine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exactly(n: usize) -> Arity {
+        Arity {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    pub fn at_least(n: usize) -> Arity {
+        Arity { min: n, max: None }
+    }
+
+    pub fn between(min: usize, max: usize) -> Arity {
+        Arity {
+            min: min,
+            max: Some(max),
+        }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && self.max.map_or(true, |max| count <= max)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+/// The parameter list of a lambda, like
+/// `(a b &optional (c 1) &rest more &key d (e 2))`. Optional and keyword
+/// parameters without a default are nil when left out, and `(a b . more)` is
+/// the same as `(a b &rest more)`.
 #[derive(Debug, PartialEq)]
+pub struct Params {
+    pub required: Vec<ast::SExpType>,
+    pub optional: Vec<(ast::SExpType, Option<ast::SExpType>)>,
+    pub rest: Option<ast::SExpType>,
+    pub key: Vec<(ast::SExpType, Option<ast::SExpType>)>,
+}
+
+// the part of a lambda list the parameters being read belong to
+#[derive(PartialEq, PartialOrd)]
+enum ParamSection {
+    Required,
+    Optional,
+    Rest,
+    Key,
+}
+
+impl Params {
+    /// Reads a lambda list, checking that every parameter is a symbol that
+    /// appears once and that the sections come in the order above.
+    pub fn parse(list: &ast::SExpType) -> Result<Params, EvalError> {
+        let items = match list.get_exp() {
+            Some(items) => items,
+            None => {
+                return Err(EvalError::new(
+                    ErrorKind::Syntax,
+                    format!("lambda arguments must be a list, got {}", list),
+                ))
+            }
+        };
+        let mut params = Params {
+            required: Vec::new(),
+            optional: Vec::new(),
+            rest: None,
+            key: Vec::new(),
+        };
+        let mut section = ParamSection::Required;
+        let mut names: Vec<String> = Vec::new();
+        for item in items {
+            let next = match item.get_identifier_name() {
+                Some(ref marker) if marker == "&optional" => Some(ParamSection::Optional),
+                Some(ref marker) if marker == "&rest" || marker == "." => Some(ParamSection::Rest),
+                Some(ref marker) if marker == "&key" => Some(ParamSection::Key),
+                _ => None,
+            };
+            if let Some(next) = next {
+                if next <= section || (section == ParamSection::Rest && params.rest.is_none()) {
+                    return Err(EvalError::new(
+                        ErrorKind::Syntax,
+                        format!("misplaced {} in lambda arguments {}", item, list),
+                    ));
+                }
+                section = next;
+                continue;
+            }
+            // optional and keyword parameters may be written (name default)
+            let (name, default) = match item.get_exp() {
+                Some(pair)
+                    if pair.len() == 2
+                        && section >= ParamSection::Optional
+                        && section != ParamSection::Rest =>
+                {
+                    (&pair[0], Some(pair[1].clone()))
+                }
+                _ => (item, None),
+            };
+            if !is_symbol(name) {
+                return Err(EvalError::new(
+                    ErrorKind::Syntax,
+                    format!("formal args must be identifiers, got {}", item),
+                ));
+            }
+            let binding = name.binding_name().unwrap();
+            if names.contains(&binding) {
+                return Err(EvalError::new(
+                    ErrorKind::Syntax,
+                    format!(
+                        "{} appears more than once in lambda arguments {}",
+                        name, list
+                    ),
+                ));
+            }
+            names.push(binding);
+            match section {
+                ParamSection::Required => params.required.push(name.clone()),
+                ParamSection::Optional => params.optional.push((name.clone(), default)),
+                ParamSection::Rest if params.rest.is_none() => params.rest = Some(name.clone()),
+                ParamSection::Rest => {
+                    return Err(EvalError::new(
+                        ErrorKind::Syntax,
+                        format!("only one rest parameter is allowed, got {}", list),
+                    ))
+                }
+                ParamSection::Key => params.key.push((name.clone(), default)),
+            }
+        }
+        if section == ParamSection::Rest && params.rest.is_none() {
+            return Err(EvalError::new(
+                ErrorKind::Syntax,
+                format!("missing rest parameter in lambda arguments {}", list),
+            ));
+        }
+        Ok(params)
+    }
+
+    /// How many arguments a call may pass. Keyword arguments come in pairs
+    /// of a keyword and its value, and a keyword may be given more than once,
+    /// so with `&key` parameters there is no most.
+    pub fn arity(&self) -> Arity {
+        if self.rest.is_some() || !self.key.is_empty() {
+            Arity::at_least(self.required.len())
+        } else {
+            Arity::between(
+                self.required.len(),
+                self.required.len() + self.optional.len(),
+            )
+        }
+    }
+}
+
+/// The Rust side of a builtin: gets the already evaluated arguments, whose
+/// count has been checked against the builtin's arity.
+pub type BuiltinFn = dyn Fn(&[Rc<IType>]) -> Result<Rc<IType>, EvalError>;
+
+/// A function implemented in Rust, see `register_builtin`.
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: String,
+    pub arity: Arity,
+    func: Rc<BuiltinFn>,
+}
+
+impl Builtin {
+    pub fn call(&self, args: &[Rc<IType>]) -> Result<Rc<IType>, EvalError> {
+        (self.func)(args)
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Builtin({}, {:?})", self.name, self.arity)
+    }
+}
+
+// two builtins are the same only if they wrap the same closure
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Builtin) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
 pub enum IType {
     Number(i64),
     Atom(String),
-    Function(
-        Rc<ast::SExpType>,
-        Rc<ast::SExpType>,
-        usize,
-        RefCell<HashMap<String, Rc<IType>>>,
-    ),
-    List(Vec<Rc<IType>>),
-    QuotedList(ast::SExpType),
+    Str(String),
+    Function(Rc<Params>, Rc<ast::SExpType>, Arity, Env),
+    Builtin(Builtin),
+    /// a macro made by defmacro, holding the function that expands it
+    Macro(Rc<IType>),
+    /// a macro made by define-syntax, expanded by matching its rules
+    SyntaxRules(SyntaxRules),
+    /// a cons cell, lists are chains of these ending in nil. Both halves can
+    /// be changed in place, by set-car! and set-cdr!
+    Pair(RefCell<Rc<IType>>, RefCell<Rc<IType>>),
     True,
     False,
     Nil,
 }
 
+// written by hand because set-cdr! can make lists that loop, which derived
+// impls would follow forever. Pairs compare like `equal` and are shown the
+// way Display writes them
+impl PartialEq for IType {
+    fn eq(&self, other: &IType) -> bool {
+        match (self, other) {
+            (&IType::Number(a), &IType::Number(b)) => a == b,
+            (&IType::Atom(ref a), &IType::Atom(ref b))
+            | (&IType::Str(ref a), &IType::Str(ref b)) => a == b,
+            (
+                &IType::Function(ref params_a, ref body_a, ref arity_a, ref env_a),
+                &IType::Function(ref params_b, ref body_b, ref arity_b, ref env_b),
+            ) => params_a == params_b && body_a == body_b && arity_a == arity_b && env_a == env_b,
+            (&IType::Builtin(ref a), &IType::Builtin(ref b)) => a == b,
+            (&IType::Macro(ref a), &IType::Macro(ref b)) => a == b,
+            (&IType::SyntaxRules(ref a), &IType::SyntaxRules(ref b)) => a == b,
+            (&IType::Pair(..), &IType::Pair(..)) => is_equal(self, other),
+            (&IType::True, &IType::True)
+            | (&IType::False, &IType::False)
+            | (&IType::Nil, &IType::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for IType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IType::Number(n) => f.debug_tuple("Number").field(&n).finish(),
+            IType::Atom(ref name) => f.debug_tuple("Atom").field(name).finish(),
+            IType::Str(ref s) => f.debug_tuple("Str").field(s).finish(),
+            IType::Function(ref params, ref body, ref arity, ref env) => f
+                .debug_tuple("Function")
+                .field(params)
+                .field(body)
+                .field(arity)
+                .field(env)
+                .finish(),
+            IType::Builtin(ref builtin) => f.debug_tuple("Builtin").field(builtin).finish(),
+            IType::Macro(ref expander) => f.debug_tuple("Macro").field(expander).finish(),
+            IType::SyntaxRules(ref rules) => f.debug_tuple("SyntaxRules").field(rules).finish(),
+            IType::Pair(..) => write!(f, "Pair({})", self),
+            IType::True => write!(f, "True"),
+            IType::False => write!(f, "False"),
+            IType::Nil => write!(f, "Nil"),
+        }
+    }
+}
+
 impl fmt::Display for IType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -28,20 +392,116 @@ impl fmt::Display for IType {
             IType::True => write!(f, "True"),
             IType::False => write!(f, "False"),
             IType::Nil => write!(f, "Nil"),
-            IType::List(ref v) => {
-                drop(write!(f, "(")); //write but drop ret val of write!
-                for i in v {
-                    drop(write!(f, " {}", i));
-                }
-                write!(f, ")")
-            }
-            IType::QuotedList(ref k) => write!(f, "{:?}", k),
+            IType::Pair(..) => write_list(f, self),
             IType::Function(_, _, _, _closure) => write!(f, "function at {:p}\n", self),
+            IType::Builtin(ref builtin) => write!(f, "builtin function {}", builtin.name),
+            IType::Macro(_) | IType::SyntaxRules(_) => write!(f, "macro at {:p}", self),
             IType::Number(n) => write!(f, "{}", n),
+            IType::Str(ref s) => write!(f, "{}", ast::quote_string(s)),
         }
     }
 }
 
+// a long or deeply nested list would otherwise be dropped one recursive call
+// per pair, so unlink the pairs nothing else holds and drop them one at a time
+impl Drop for IType {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        match *self {
+            IType::Pair(ref mut car, ref mut cdr) => {
+                unlink_pair(car.get_mut(), &mut pending);
+                unlink_pair(cdr.get_mut(), &mut pending);
+            }
+            // a closure may be the last thing outside its scope that holds it
+            IType::Function(_, _, _, ref env) => return release_scope(env),
+            _ => return,
+        }
+        while let Some(value) = pending.pop() {
+            if let Ok(mut pair) = Rc::try_unwrap(value) {
+                if let IType::Pair(ref mut car, ref mut cdr) = pair {
+                    unlink_pair(car.get_mut(), &mut pending);
+                    unlink_pair(cdr.get_mut(), &mut pending);
+                }
+            }
+        }
+    }
+}
+
+// moves `half` onto `pending` when it is a pair, leaving nil in its place
+fn unlink_pair(half: &mut Rc<IType>, pending: &mut Vec<Rc<IType>>) {
+    if let IType::Pair(..) = **half {
+        pending.push(mem::replace(half, Rc::new(IType::Nil)));
+    }
+}
+
+// a list being written: the pair it starts at, its items still to go and
+// its tail
+type OpenList = (*const IType, vec::IntoIter<Rc<IType>>, Rc<IType>);
+
+// writes `list` with the lists nested in it kept on a stack of its own, so
+// that deep nesting can't overflow the native one. A list that turns up
+// inside itself is written as `...`, and so is the rest of one whose cdrs
+// loop back around
+fn write_list(f: &mut fmt::Formatter, list: &IType) -> fmt::Result {
+    let mut open = vec![open_list(f, list)?];
+    let mut inside = HashSet::new();
+    inside.insert(list as *const IType);
+    while let Some(next) = open.last_mut().map(|list| list.1.next()) {
+        match next {
+            Some(item) => {
+                write!(f, " ")?;
+                match *item {
+                    IType::Pair(..) if inside.contains(&(&*item as *const IType)) => {
+                        write!(f, "...")?
+                    }
+                    IType::Pair(..) => {
+                        inside.insert(&*item as *const IType);
+                        let list = open_list(f, &item)?;
+                        open.push(list);
+                    }
+                    _ => write!(f, "{}", item)?,
+                }
+            }
+            None => {
+                let (pair, _, tail) = open.pop().unwrap();
+                inside.remove(&pair);
+                match *tail {
+                    IType::Nil => write!(f, ")")?,
+                    IType::Pair(..) => write!(f, " . ...)")?,
+                    _ => write!(f, " . {})", tail)?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// writes the start of the pair `list` and returns its items and tail
+fn open_list(f: &mut fmt::Formatter, list: &IType) -> Result<OpenList, fmt::Error> {
+    let (car, cdr) = match *list {
+        IType::Pair(ref car, ref cdr) => (Rc::clone(&car.borrow()), Rc::clone(&cdr.borrow())),
+        _ => unreachable!("open_list called on {:?}", list),
+    };
+    let (mut items, mut tail) = list_parts(&cdr);
+    // when the cdrs loop back around to `list` itself, the walk from its
+    // cdr ends with the car of `list` again
+    if let IType::Pair(..) = *tail {
+        if Rc::ptr_eq(&tail, &cdr) {
+            let mut pair = Rc::clone(&cdr);
+            for _ in 0..items.len() {
+                if ptr::eq(&*pair, list) {
+                    items.pop();
+                    tail = pair;
+                    break;
+                }
+                pair = cdr_of(&pair);
+            }
+        }
+    }
+    items.insert(0, car);
+    write!(f, "(")?;
+    Ok((list, items.into_iter(), tail))
+}
 impl IType {
     pub fn get_fn(&self) -> Option<&IType> {
         match *self {
@@ -51,9 +511,32 @@ impl IType {
     }
 }
 
-const KEYWORDS: [&'static str; 13] = [
-    "false", "true", "nil", "quote", "car", "cdr", "cons", "atom", "equal", "cond", "label",
-    "lambda", "defun",
+const KEYWORDS: [&'static str; 25] = [
+    "false",
+    "true",
+    "nil",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "car",
+    "cdr",
+    "cons",
+    "atom",
+    "equal",
+    "eq",
+    "cond",
+    "label",
+    "lambda",
+    "defun",
+    "defmacro",
+    "define-syntax",
+    "syntax-rules",
+    "let",
+    "let*",
+    "letrec",
+    "begin",
+    "set!",
 ];
 lazy_static! {
     static ref KEYWORD_SET: Vec<String> = KEYWORDS.iter().clone().map(|x| x.to_string()).collect();
@@ -70,13 +553,237 @@ fn is_fn(f: &IType) -> bool {
     }
 }
 
-pub fn make_env() -> HashMap<String, Rc<IType>> {
-    let v = HashMap::new();
+/// One scope of bindings. A lookup that misses here carries on in `parent`,
+/// so a closure keeps the scope it was made in alive by holding a reference
+/// to it instead of a copy, and sees later changes to it.
+pub struct Environment {
+    vars: RefCell<HashMap<String, Rc<IType>>>,
+    parent: Option<Env>,
+}
+
+pub type Env = Rc<Environment>;
+
+impl Environment {
+    pub fn new() -> Env {
+        Rc::new(Environment {
+            vars: RefCell::new(HashMap::new()),
+            parent: None,
+        })
+    }
+
+    /// A new, empty scope nested inside `parent`.
+    pub fn extend(parent: &Env) -> Env {
+        Rc::new(Environment {
+            vars: RefCell::new(HashMap::new()),
+            parent: Some(Rc::clone(parent)),
+        })
+    }
+
+    pub fn parent(&self) -> Option<&Env> {
+        self.parent.as_ref()
+    }
+
+    /// Looks `name` up in this scope and then in each enclosing one.
+    pub fn get(&self, name: &str) -> Option<Rc<IType>> {
+        let mut scope = self;
+        loop {
+            if let Some(value) = scope.vars.borrow().get(name) {
+                return Some(Rc::clone(value));
+            }
+            match scope.parent {
+                Some(ref parent) => scope = parent,
+                None => return None,
+            }
+        }
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Binds `name` in this scope, shadowing any binding further out.
+    pub fn insert(&self, name: String, value: Rc<IType>) -> Option<Rc<IType>> {
+        self.vars.borrow_mut().insert(name, value)
+    }
+
+    /// Changes the nearest binding of `name`, in this scope or an enclosing
+    /// one, to `value` and returns what it was. Returns `None`, and binds
+    /// nothing, when `name` isn't bound anywhere.
+    pub fn set(&self, name: &str, value: Rc<IType>) -> Option<Rc<IType>> {
+        let mut scope = self;
+        loop {
+            if let Some(slot) = scope.vars.borrow_mut().get_mut(name) {
+                return Some(mem::replace(slot, value));
+            }
+            match scope.parent {
+                Some(ref parent) => scope = parent,
+                None => return None,
+            }
+        }
+    }
+}
+
+// a closure stored in a scope refers back to that scope, so only print the
+// names bound here rather than following values around the cycle
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let vars = self.vars.borrow();
+        let mut names: Vec<&String> = vars.keys().collect();
+        names.sort();
+        write!(
+            f,
+            "Environment {{ names: {:?}, has_parent: {} }}",
+            names,
+            self.parent.is_some()
+        )
+    }
+}
+
+// scopes are compared by identity, two scopes with the same bindings are
+// still different scopes
+// a scope may be the last thing outside its parent that holds it
+impl Drop for Environment {
+    fn drop(&mut self) {
+        if let Some(ref parent) = self.parent {
+            release_scope(parent);
+        }
+    }
+}
+
+/// Called with a reference to `scope` that is about to be let go of. A
+/// function bound in a scope it captures, as letrec and a defun inside a
+/// body make, keeps that scope alive and is kept alive by it, so neither
+/// would ever be freed. When nothing but such functions, held by nothing
+/// else, would be left holding `scope`, its bindings are cleared to break
+/// those cycles. Cycles that run through anything else, like a list of
+/// closures, are still leaked.
+fn release_scope(scope: &Env) {
+    // the global scope lives as long as the functions defined in it
+    if scope.parent.is_none() {
+        return;
+    }
+    let holders = Rc::strong_count(scope) - 1;
+    let cleared = match scope.vars.try_borrow_mut() {
+        Ok(ref mut vars) if holders > 0 && holders <= vars.len() => {
+            let captured_here = vars
+                .values()
+                .filter(|value| match ***value {
+                    IType::Function(_, _, _, ref env) => {
+                        Rc::ptr_eq(env, scope) && Rc::strong_count(value) == 1
+                    }
+                    _ => false,
+                })
+                .count();
+            if captured_here != holders {
+                return;
+            }
+            mem::replace(&mut **vars, HashMap::new())
+        }
+        _ => return,
+    };
+    // dropped here, once the bindings are no longer borrowed
+    drop(cleared);
+}
+
+impl PartialEq for Environment {
+    fn eq(&self, other: &Environment) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+// the outermost scope that `env` is nested in
+fn global_env(env: &Env) -> Env {
+    let mut scope = Rc::clone(env);
+    while let Some(parent) = scope.parent().cloned() {
+        scope = parent;
+    }
+    scope
+}
+
+pub fn make_env() -> Env {
+    let v = Environment::new();
+    // the numeric primitives are ordinary functions, so they can be passed
+    // around and redefined like any other
+    let arithmetic_ops = [
+        ("+", Arity::at_least(0)),
+        ("*", Arity::at_least(0)),
+        ("-", Arity::at_least(1)),
+        ("/", Arity::at_least(1)),
+        ("mod", Arity::exactly(2)),
+    ];
+    for &(op, arity) in &arithmetic_ops {
+        define_builtin(&v, op, arity, move |args| {
+            let numbers = numbers_of(op, args)?;
+            arithmetic(op, &numbers)
+                .map(|result| Rc::new(IType::Number(result)))
+                .map_err(|message| EvalError::new(ErrorKind::Arithmetic, message))
+        });
+    }
+    for &op in &["<", ">", "=", "<=", ">="] {
+        define_builtin(&v, op, Arity::at_least(1), move |args| {
+            Ok(to_bool(compare(op, &numbers_of(op, args)?)))
+        });
+    }
+    for &(name, is_car) in &[("set-car!", true), ("set-cdr!", false)] {
+        define_builtin(&v, name, Arity::exactly(2), move |args| match *args[0] {
+            IType::Pair(ref car, ref cdr) => {
+                let half = if is_car { car } else { cdr };
+                half.replace(Rc::clone(&args[1]));
+                Ok(Rc::new(IType::Nil))
+            }
+            _ => Err(EvalError::new(
+                ErrorKind::Type,
+                format!("{} expects a pair, got {}", name, args[0]),
+            )),
+        });
+    }
+    // defmacro binds macros in the global scope, so that is where these look
+    // them up. The reference is weak so that the scope and the builtins
+    // stored in it don't keep each other alive
+    for &(name, repeat) in &[("macroexpand-1", false), ("macroexpand", true)] {
+        let global = Rc::downgrade(&v);
+        define_builtin(&v, name, Arity::exactly(1), move |args| {
+            match global.upgrade() {
+                Some(env) => macroexpand(&env, &args[0], repeat),
+                None => Err(EvalError::new(
+                    ErrorKind::Builtin,
+                    format!("{} outlived its global scope", name),
+                )),
+            }
+        });
+    }
     v
 }
+
+/// Binds `name` in `env` to a function implemented by `func`, so scripts can
+/// call into the host application. Calls with an argument count that `arity`
+/// does not accept fail before `func` runs, and the messages `func` fails
+/// with, which can name the values it was given, become errors of kind
+/// `ErrorKind::Builtin`.
+pub fn register_builtin<F>(env: &Env, name: &str, arity: Arity, func: F)
+where
+    F: Fn(&[Rc<IType>]) -> Result<Rc<IType>, String> + 'static,
+{
+    define_builtin(env, name, arity, move |args| {
+        func(args).map_err(|message| EvalError::new(ErrorKind::Builtin, message))
+    });
+}
+
+// binds a builtin whose errors are already eval errors
+fn define_builtin<F>(env: &Env, name: &str, arity: Arity, func: F)
+where
+    F: Fn(&[Rc<IType>]) -> Result<Rc<IType>, EvalError> + 'static,
+{
+    let builtin = Builtin {
+        name: name.to_string(),
+        arity: arity,
+        func: Rc::new(func),
+    };
+    env.insert(name.to_string(), Rc::new(IType::Builtin(builtin)));
+}
 pub fn is_symbol(exp: &ast::SExpType) -> bool {
     match exp {
-        &ast::SExpType::Identifier(ref n) => !is_keyword(n),
+        &ast::SExpType::Identifier(ref n) | &ast::SExpType::Renamed(ref n, _) => !is_keyword(n),
         _ => false,
     }
 }
@@ -89,6 +796,80 @@ pub fn truthy(st: &String) -> bool {
     }
 }
 
+/// Only `false` and `nil` (which is also the empty list) count as false,
+/// every other value is true.
+pub fn is_truthy(value: &IType) -> bool {
+    match *value {
+        IType::False | IType::Nil => false,
+        _ => true,
+    }
+}
+
+/// Structural equality: numbers, atoms and strings compare by value and
+/// lists item by item.
+/// Functions are only equal to themselves. Lists that contain themselves are
+/// equal when no walk through both finds a difference.
+pub fn is_equal(a: &IType, b: &IType) -> bool {
+    // the pairs left to compare are kept on a stack rather than recursed
+    // into, and pairs already being compared are taken to be equal so that
+    // walking around a loop ends
+    let mut pending = Vec::new();
+    let mut compared = HashSet::new();
+    if !equal_step(a, b, &mut pending, &mut compared) {
+        return false;
+    }
+    while let Some((x, y)) = pending.pop() {
+        if !equal_step(&x, &y, &mut pending, &mut compared) {
+            return false;
+        }
+    }
+    true
+}
+
+// compares `a` and `b` but for the halves of pairs, which go on `pending`
+fn equal_step(
+    a: &IType,
+    b: &IType,
+    pending: &mut Vec<(Rc<IType>, Rc<IType>)>,
+    compared: &mut HashSet<(*const IType, *const IType)>,
+) -> bool {
+    match (a, b) {
+        (&IType::Pair(ref car_a, ref cdr_a), &IType::Pair(ref car_b, ref cdr_b)) => {
+            if !ptr::eq(a, b) && compared.insert((a as *const IType, b as *const IType)) {
+                pending.push((Rc::clone(&cdr_a.borrow()), Rc::clone(&cdr_b.borrow())));
+                pending.push((Rc::clone(&car_a.borrow()), Rc::clone(&car_b.borrow())));
+            }
+            true
+        }
+        (&IType::Function(..), &IType::Function(..)) => ptr::eq(a, b),
+        (&IType::Macro(..), &IType::Macro(..)) => ptr::eq(a, b),
+        (&IType::SyntaxRules(..), &IType::SyntaxRules(..)) => ptr::eq(a, b),
+        (&IType::Builtin(ref x), &IType::Builtin(ref y)) => x == y,
+        _ => a == b,
+    }
+}
+
+/// Identity: true when both are the very same object. `true`, `false` and
+/// `nil` have no identity of their own and are always eq to themselves, and
+/// atoms are eq whenever they have the same name.
+pub fn is_eq(a: &Rc<IType>, b: &Rc<IType>) -> bool {
+    match (&**a, &**b) {
+        (&IType::True, &IType::True)
+        | (&IType::False, &IType::False)
+        | (&IType::Nil, &IType::Nil) => true,
+        (&IType::Atom(ref x), &IType::Atom(ref y)) => x == y,
+        _ => Rc::ptr_eq(a, b),
+    }
+}
+
+fn to_bool(b: bool) -> Rc<IType> {
+    if b {
+        Rc::new(IType::True)
+    } else {
+        Rc::new(IType::False)
+    }
+}
+
 pub fn is_nil(st: &String) -> bool {
     match st.as_ref() {
         "Nil" => true,
@@ -110,249 +891,1108 @@ pub fn is_atom(exp: &String) -> bool {
 
 pub fn get_first_term(exp: &ast::SExpType) -> String {
     match *exp {
-        ast::SExpType::Exp(ref form) => match (*form)[0] {
-            ast::SExpType::Identifier(ref name) => name.clone(),
+        ast::SExpType::Exp(ref form) => match form.first() {
+            Some(&ast::SExpType::Identifier(ref name))
+            | Some(&ast::SExpType::Renamed(ref name, _)) => name.clone(),
             _ => "".to_string(),
         },
-        ast::SExpType::Identifier(ref n) => n.clone(),
+        ast::SExpType::Identifier(ref n) | ast::SExpType::Renamed(ref n, _) => n.clone(),
         ast::SExpType::Number(k) => k.to_string(),
+        ast::SExpType::Str(_) => "".to_string(),
     }
 }
 
-pub fn eval(
-    env: &mut HashMap<String, Rc<IType>>,
-    exp: &ast::SExpType,
-) -> Result<Rc<IType>, &'static str> {
+/// Turns quoted syntax into plain data: identifiers become atoms, except for
+/// `nil`, `true` and `false`, which stay the constants they name, and
+/// expressions become lists.
+pub fn quoted_to_value(exp: &ast::SExpType) -> Rc<IType> {
     match *exp {
-        ast::SExpType::Identifier(ref name) => {
-            // if name is True, False or Nil, return that
-            // if it is a variable, return the value of the variable
-            // else if name is not a keyword, return Atom(name
-            // return error other wise
-            let v = if is_keyword(name) {
-                match name.as_str() {
-                    "true" => Ok(Rc::new(IType::True)),
-                    "false" => Ok(Rc::new(IType::False)),
-                    "nil" => Ok(Rc::new(IType::Nil)),
-                    _ => Err("cannot eval keyword"),
+        ast::SExpType::Identifier(ref name) | ast::SExpType::Renamed(ref name, _) => {
+            match name.as_str() {
+                "nil" => Rc::new(IType::Nil),
+                "true" => Rc::new(IType::True),
+                "false" => Rc::new(IType::False),
+                _ => Rc::new(IType::Atom(name.clone())),
+            }
+        }
+        ast::SExpType::Number(n) => Rc::new(IType::Number(n)),
+        ast::SExpType::Str(ref s) => Rc::new(IType::Str(s.clone())),
+        ast::SExpType::Exp(ref items) if items.is_empty() => Rc::new(IType::Nil),
+        // (a b . c) is the list of a and b ending in c rather than nil
+        ast::SExpType::Exp(ref items) if items.len() >= 3 && is_dot(&items[items.len() - 2]) => {
+            let (last, front) = items.split_last().unwrap();
+            list_with_tail(
+                front[..front.len() - 1]
+                    .iter()
+                    .map(quoted_to_value)
+                    .collect(),
+                quoted_to_value(last),
+            )
+        }
+        ast::SExpType::Exp(ref items) => list_from(items.iter().map(quoted_to_value).collect()),
+    }
+}
+
+fn is_dot(exp: &ast::SExpType) -> bool {
+    exp.get_identifier_name().map_or(false, |name| name == ".")
+}
+
+/// A new pair holding `car` and `cdr`.
+pub fn cons(car: Rc<IType>, cdr: Rc<IType>) -> Rc<IType> {
+    Rc::new(IType::Pair(RefCell::new(car), RefCell::new(cdr)))
+}
+
+/// The list of `items`, nil when there are none.
+pub fn list_from(items: Vec<Rc<IType>>) -> Rc<IType> {
+    list_with_tail(items, Rc::new(IType::Nil))
+}
+
+/// The list of `items` whose last pair has `tail` as its cdr, so a dotted
+/// list unless `tail` is nil.
+pub fn list_with_tail(items: Vec<Rc<IType>>, tail: Rc<IType>) -> Rc<IType> {
+    items
+        .into_iter()
+        .rev()
+        .fold(tail, |list, item| cons(item, list))
+}
+
+/// Splits `value` into the cars of the chain of pairs it starts and the cdr
+/// of the last one, nil for a proper list. Anything that isn't a pair has
+/// no items and is its own tail. When set-cdr! has made the chain loop, the
+/// items stop before they repeat and the tail is the pair the loop starts at.
+pub fn list_parts(value: &Rc<IType>) -> (Vec<Rc<IType>>, Rc<IType>) {
+    let mut items = Vec::new();
+    let mut tail = Rc::clone(value);
+    // `slow` takes one step for every two of `tail`, and only a loop lets
+    // `tail` come around to meet it
+    let mut slow = Rc::clone(value);
+    loop {
+        let next = match *tail {
+            IType::Pair(ref car, ref cdr) => {
+                items.push(Rc::clone(&car.borrow()));
+                Rc::clone(&cdr.borrow())
+            }
+            _ => break,
+        };
+        tail = next;
+        if items.len() % 2 == 0 {
+            slow = cdr_of(&slow);
+            if Rc::ptr_eq(&slow, &tail) {
+                // the loop starts where a walk from the head meets one from
+                // here, and ends when it comes back to that start
+                let mut start = Rc::clone(value);
+                let mut before = 0;
+                while !Rc::ptr_eq(&start, &slow) {
+                    start = cdr_of(&start);
+                    slow = cdr_of(&slow);
+                    before += 1;
                 }
-            } else if env.contains_key(name) {
-                let val = env.get(name).unwrap();
-                Ok(Rc::clone(val))
-            } else if is_atom_type(name) {
-                Ok(Rc::new(IType::Atom(name.clone())))
-            } else {
-                Err("undefined value")
+                let mut length = 1;
+                let mut pair = cdr_of(&start);
+                while !Rc::ptr_eq(&pair, &start) {
+                    pair = cdr_of(&pair);
+                    length += 1;
+                }
+                items.truncate(before + length);
+                return (items, start);
+            }
+        }
+    }
+    (items, tail)
+}
+
+// the cdr of `pair`, which has to be one
+fn cdr_of(pair: &Rc<IType>) -> Rc<IType> {
+    match **pair {
+        IType::Pair(_, ref cdr) => Rc::clone(&cdr.borrow()),
+        _ => unreachable!("cdr_of called on {}", pair),
+    }
+}
+
+/// The items of `value` if it is a proper list, nil included.
+pub fn list_items(value: &Rc<IType>) -> Option<Vec<Rc<IType>>> {
+    match list_parts(value) {
+        (items, ref tail) if **tail == IType::Nil => Some(items),
+        _ => None,
+    }
+}
+
+/// Turns a value back into code, the inverse of `quoted_to_value`. Values
+/// that have no written form, like functions, can't be turned into code, and
+/// neither can lists that loop or nest deeper than the reader allows.
+pub fn value_to_sexp(value: &IType) -> Result<ast::SExpType, EvalError> {
+    nested_value_to_sexp(value, 0)
+}
+
+// `depth` counts the lists `value` sits in, which also stops the walk into a
+// list that contains itself
+fn nested_value_to_sexp(value: &IType, depth: usize) -> Result<ast::SExpType, EvalError> {
+    match *value {
+        IType::Atom(ref name) => Ok(ast::SExpType::Identifier(name.clone())),
+        IType::Number(n) => Ok(ast::SExpType::Number(n)),
+        IType::Str(ref s) => Ok(ast::SExpType::Str(s.clone())),
+        IType::True => Ok(ast::SExpType::Identifier("true".to_string())),
+        IType::False => Ok(ast::SExpType::Identifier("false".to_string())),
+        IType::Nil => Ok(ast::SExpType::Exp(vec![])),
+        IType::Pair(..) if depth == ast::max_depth() => Err(EvalError::new(
+            ErrorKind::Depth,
+            format!(
+                "cannot turn a list nested deeper than {} into code",
+                ast::max_depth()
+            ),
+        )),
+        IType::Pair(ref car, ref cdr) => {
+            let (items, tail) = list_parts(&cdr.borrow());
+            if let IType::Pair(..) = *tail {
+                return Err(EvalError::new(
+                    ErrorKind::Type,
+                    "cannot turn a list that loops into code",
+                ));
+            }
+            let mut exps = Vec::with_capacity(items.len() + 3);
+            exps.push(nested_value_to_sexp(&car.borrow(), depth + 1)?);
+            for item in items {
+                exps.push(nested_value_to_sexp(&item, depth + 1)?);
+            }
+            if *tail != IType::Nil {
+                exps.push(ast::SExpType::Identifier(".".to_string()));
+                exps.push(nested_value_to_sexp(&tail, depth + 1)?);
+            }
+            Ok(ast::SExpType::Exp(exps))
+        }
+        IType::Function(..) | IType::Builtin(_) | IType::Macro(_) | IType::SyntaxRules(_) => Err(
+            EvalError::new(ErrorKind::Type, format!("cannot turn {} into code", value)),
+        ),
+    }
+}
+
+// if `exp` is `(name arg)`, returns arg
+fn unquote_arg<'a>(exp: &'a ast::SExpType, name: &str) -> Option<&'a ast::SExpType> {
+    match exp.get_exp() {
+        Some(items) if items.len() == 2 && get_first_term(exp) == name => Some(&items[1]),
+        _ => None,
+    }
+}
+
+// builds the value of a quasiquote template. `depth` counts the quasiquotes
+// that `template` sits in, only unquotes that bring it back to 0 are evaluated
+fn quasiquote(env: &Env, template: &ast::SExpType, depth: usize) -> Result<Rc<IType>, EvalError> {
+    let items = match *template {
+        ast::SExpType::Exp(ref items) => items,
+        _ => return Ok(quoted_to_value(template)),
+    };
+    if let Some(inner) = unquote_arg(template, "unquote") {
+        if depth == 1 {
+            return eval(env, inner);
+        }
+        let inner_value = quasiquote(env, inner, depth - 1)?;
+        return Ok(list_from(vec![
+            Rc::new(IType::Atom("unquote".to_string())),
+            inner_value,
+        ]));
+    }
+    if unquote_arg(template, "unquote-splicing").is_some() && depth == 1 {
+        return Err(EvalError::new(
+            ErrorKind::Syntax,
+            "unquote-splicing must appear inside a list",
+        ));
+    }
+    let inner_depth = if unquote_arg(template, "quasiquote").is_some() {
+        depth + 1
+    } else {
+        depth
+    };
+    // `(a . ,b) ends in the value of its last item rather than nil
+    let (items, tail) = if items.len() >= 3 && is_dot(&items[items.len() - 2]) {
+        let tail = quasiquote(env, &items[items.len() - 1], inner_depth)?;
+        (&items[..items.len() - 2], tail)
+    } else {
+        (&items[..], Rc::new(IType::Nil))
+    };
+    let mut values: Vec<Rc<IType>> = Vec::new();
+    for item in items {
+        match unquote_arg(item, "unquote-splicing") {
+            Some(inner) if inner_depth == 1 => {
+                let spliced = eval(env, inner)?;
+                let not_a_list = || {
+                    EvalError::new(
+                        ErrorKind::Type,
+                        format!("unquote-splicing needs a list, got {}", spliced),
+                    )
+                };
+                match list_items(&spliced) {
+                    Some(items) => values.extend(items),
+                    None => return Err(not_a_list()),
+                }
+            }
+            Some(inner) => {
+                let inner_value = quasiquote(env, inner, inner_depth - 1)?;
+                values.push(list_from(vec![
+                    Rc::new(IType::Atom("unquote-splicing".to_string())),
+                    inner_value,
+                ]));
+            }
+            None => values.push(quasiquote(env, item, inner_depth)?),
+        }
+    }
+    Ok(list_with_tail(values, tail))
+}
+
+// the arguments of a numeric builtin, all of which must be numbers
+fn numbers_of(op: &str, args: &[Rc<IType>]) -> Result<Vec<i64>, EvalError> {
+    let mut numbers = Vec::with_capacity(args.len());
+    for value in args {
+        match **value {
+            IType::Number(n) => numbers.push(n),
+            _ => {
+                return Err(EvalError::new(
+                    ErrorKind::Type,
+                    format!("{} expects numbers, got {}", op, value),
+                ))
+            }
+        }
+    }
+    Ok(numbers)
+}
+
+/// Folds `numbers` with the arithmetic operator `op`. `-` and `/` with a
+/// single argument negate and invert it.
+pub fn arithmetic(op: &str, numbers: &[i64]) -> Result<i64, &'static str> {
+    let overflow = "integer overflow";
+    match op {
+        "+" => numbers
+            .iter()
+            .try_fold(0i64, |acc, &n| acc.checked_add(n))
+            .ok_or(overflow),
+        "*" => numbers
+            .iter()
+            .try_fold(1i64, |acc, &n| acc.checked_mul(n))
+            .ok_or(overflow),
+        "-" => match numbers.split_first() {
+            None => Err("- needs at least one argument"),
+            Some((&first, [])) => first.checked_neg().ok_or(overflow),
+            Some((&first, rest)) => rest
+                .iter()
+                .try_fold(first, |acc, &n| acc.checked_sub(n))
+                .ok_or(overflow),
+        },
+        "/" => {
+            let (first, rest) = match numbers.split_first() {
+                None => return Err("/ needs at least one argument"),
+                Some((_, [])) => (1, &numbers[..]),
+                Some((&first, rest)) => (first, rest),
             };
-            return v;
+            let mut acc = first;
+            for &n in rest {
+                if n == 0 {
+                    return Err("division by zero");
+                }
+                acc = acc.checked_div(n).ok_or(overflow)?;
+            }
+            Ok(acc)
         }
-        ast::SExpType::Exp(ref n) => {
-            match get_first_term(&n[0]).as_ref() {
-                "atom" => {
-                    if n.len() != 2 {
-                        Err("incorrect no. of arguments to atom. should be (atom something)")
-                    } else {
-                        match n[1] {
-                            ast::SExpType::Identifier(ref _atom_name) => Ok(Rc::new(IType::True)),
-                            _ => Ok(Rc::new(IType::False)),
-                        }
-                    }
+        "mod" => {
+            if numbers.len() != 2 {
+                return Err("mod takes exactly two arguments");
+            }
+            let (a, b) = (numbers[0], numbers[1]);
+            if b == 0 {
+                return Err("division by zero");
+            }
+            // the result takes the sign of the divisor
+            let r = a.checked_rem(b).ok_or(overflow)?;
+            if r != 0 && (r < 0) != (b < 0) {
+                Ok(r + b)
+            } else {
+                Ok(r)
+            }
+        }
+        _ => Err("unknown arithmetic operator"),
+    }
+}
+
+/// True when every adjacent pair of `numbers` satisfies the comparison `op`,
+/// so `(< 1 2 3)` checks 1 < 2 and 2 < 3.
+pub fn compare(op: &str, numbers: &[i64]) -> bool {
+    numbers.windows(2).all(|pair| match op {
+        "<" => pair[0] < pair[1],
+        ">" => pair[0] > pair[1],
+        "<=" => pair[0] <= pair[1],
+        ">=" => pair[0] >= pair[1],
+        _ => pair[0] == pair[1],
+    })
+}
+
+// checks the formal args and body of a lambda or defun and builds the function
+fn make_function(
+    lambda_args: &ast::SExpType,
+    lambda_body: &[ast::SExpType],
+    captured_env: Env,
+) -> Result<Rc<IType>, EvalError> {
+    let params = Params::parse(lambda_args)?;
+    // a body of several forms is kept as one (begin ...) form
+    let body = match lambda_body.len() {
+        0 => return bad_syntax("lambda body must have at least one form"),
+        1 => lambda_body[0].clone(),
+        _ => {
+            let mut forms = Vec::with_capacity(lambda_body.len() + 1);
+            forms.push(ast::SExpType::Identifier("begin".to_string()));
+            forms.extend_from_slice(lambda_body);
+            ast::SExpType::Exp(forms)
+        }
+    };
+    let arity = params.arity();
+    Ok(Rc::new(IType::Function(
+        Rc::new(params),
+        Rc::new(body),
+        arity,
+        captured_env,
+    )))
+}
+
+// evaluates all but the last of `forms` for their effects and returns the
+// last one, which the caller evaluates in tail position
+fn eval_sequence<'a>(
+    env: &Env,
+    forms: &'a [ast::SExpType],
+) -> Result<&'a ast::SExpType, EvalError> {
+    let (last, leading) = forms.split_last().unwrap();
+    for form in leading {
+        eval(env, form)?;
+    }
+    Ok(last)
+}
+
+// the scope a call to a function runs its body in: the arguments bound to
+// the formal args, inside the scope the function was defined in. Defaults
+// for missing arguments are evaluated in that scope, so they can use the
+// parameters before them
+fn call_scope(
+    name: &str,
+    params: &Params,
+    arity: Arity,
+    captured_env: &Env,
+    args: &[Rc<IType>],
+) -> Result<Env, EvalError> {
+    if !arity.accepts(args.len()) {
+        return Err(EvalError::new(
+            ErrorKind::Arity,
+            format!(
+                "wrong number of arguments to {}: expected {}, got {}",
+                name,
+                arity,
+                args.len()
+            ),
+        ));
+    }
+    let call_env = Environment::extend(captured_env);
+    let mut args = args.iter().cloned();
+    for formal_arg in &params.required {
+        call_env.insert(formal_arg.binding_name().unwrap(), args.next().unwrap());
+    }
+    for &(ref formal_arg, ref default) in &params.optional {
+        let value = match args.next() {
+            Some(value) => value,
+            None => default_value(&call_env, default)?,
+        };
+        call_env.insert(formal_arg.binding_name().unwrap(), value);
+    }
+    let rest: Vec<Rc<IType>> = args.collect();
+    if let Some(ref formal_arg) = params.rest {
+        call_env.insert(formal_arg.binding_name().unwrap(), list_from(rest.clone()));
+    }
+    if !params.key.is_empty() {
+        bind_keys(name, params, &call_env, &rest)?;
+    }
+    Ok(call_env)
+}
+
+fn default_value(env: &Env, default: &Option<ast::SExpType>) -> Result<Rc<IType>, EvalError> {
+    match *default {
+        Some(ref exp) => eval(env, exp),
+        None => Ok(Rc::new(IType::Nil)),
+    }
+}
+
+// binds the keyword parameters from `args`, the arguments after the
+// positional ones, which come in pairs like `:name value`
+fn bind_keys(
+    name: &str,
+    params: &Params,
+    call_env: &Env,
+    args: &[Rc<IType>],
+) -> Result<(), EvalError> {
+    if args.len() % 2 != 0 {
+        return Err(EvalError::new(
+            ErrorKind::Arity,
+            format!("odd number of keyword arguments to {}", name),
+        ));
+    }
+    let mut given: HashMap<String, Rc<IType>> = HashMap::new();
+    for pair in args.chunks(2) {
+        let keyword = match *pair[0] {
+            IType::Atom(ref keyword) if is_atom_type(keyword) => keyword[1..].to_string(),
+            _ => {
+                return Err(EvalError::new(
+                    ErrorKind::Type,
+                    format!(
+                        "expected a keyword in the arguments to {}, got {}",
+                        name, pair[0]
+                    ),
+                ))
+            }
+        };
+        if !params
+            .key
+            .iter()
+            .any(|&(ref formal_arg, _)| formal_arg.get_identifier_name().unwrap() == keyword)
+        {
+            return Err(EvalError::new(
+                ErrorKind::Arity,
+                format!("unknown keyword argument :{} to {}", keyword, name),
+            ));
+        }
+        // the first of repeated keywords wins
+        given.entry(keyword).or_insert_with(|| Rc::clone(&pair[1]));
+    }
+    for &(ref formal_arg, ref default) in &params.key {
+        let value = match given.remove(&formal_arg.get_identifier_name().unwrap()) {
+            Some(value) => value,
+            None => default_value(call_env, default)?,
+        };
+        call_env.insert(formal_arg.binding_name().unwrap(), value);
+    }
+    Ok(())
+}
+
+// runs a builtin on arguments whose count hasn't been checked yet
+fn call_builtin(builtin: &Builtin, args: &[Rc<IType>]) -> Result<Rc<IType>, EvalError> {
+    if !builtin.arity.accepts(args.len()) {
+        return Err(EvalError::new(
+            ErrorKind::Arity,
+            format!(
+                "wrong number of arguments to {}: expected {}, got {}",
+                builtin.name,
+                builtin.arity,
+                args.len()
+            ),
+        ));
+    }
+    builtin.call(args)
+}
+
+/// Calls `func` with arguments that are already evaluated. A function's body
+/// runs in a fresh scope made for this call alone and layered over the scope
+/// the function was defined in, so calls never see each other's arguments,
+/// never change the function itself, and can nest and recurse freely.
+pub fn apply(func: &IType, args: Vec<Rc<IType>>) -> Result<Rc<IType>, EvalError> {
+    apply_named("lambda", func, &args)
+}
+
+// `apply`, with the name errors should call the function by
+fn apply_named(name: &str, func: &IType, args: &[Rc<IType>]) -> Result<Rc<IType>, EvalError> {
+    match enter_call(name, func, args)? {
+        Callee::Body(call_env, body) => {
+            let scope = CurrentScope(call_env);
+            eval(&scope.0, &body)
+        }
+        Callee::Value(value) => Ok(value),
+    }
+}
+
+// where a call goes once it has started: the body of a function, to be
+// evaluated in the scope made for the call, or the value a builtin returned
+enum Callee {
+    Body(Env, Rc<ast::SExpType>),
+    Value(Rc<IType>),
+}
+
+// starts calling `func`, binding a function's arguments or running a
+// builtin. A builtin that fails is put on the error's backtrace here, which
+// is the only time its arguments need copying into a frame
+fn enter_call(name: &str, func: &IType, args: &[Rc<IType>]) -> Result<Callee, EvalError> {
+    match *func {
+        IType::Function(ref params, ref body, arity, ref captured_env) => {
+            let call_env = call_scope(name, params, arity, captured_env, args)?;
+            Ok(Callee::Body(call_env, Rc::clone(body)))
+        }
+        IType::Builtin(ref builtin) => {
+            call_builtin(builtin, args).map(Callee::Value).map_err(|e| {
+                e.called_from(Some(Frame {
+                    function: name.to_string(),
+                    args: args.to_vec(),
+                }))
+            })
+        }
+        _ => Err(EvalError::new(
+            ErrorKind::Type,
+            format!("cannot apply {}, it is not a function", func),
+        )),
+    }
+}
+depth_limit! {
+    /// How deeply evaluation may nest when no other limit has been set, see
+    /// `eval`.
+    default = 1000
+}
+
+thread_local! {
+    static DEPTH: Cell<usize> = Cell::new(0);
+}
+
+// one level of eval nesting, given back when the guard is dropped
+struct DepthGuard;
+
+impl DepthGuard {
+    fn enter() -> Result<DepthGuard, EvalError> {
+        DEPTH.with(|depth| {
+            if depth.get() >= max_depth() {
+                Err(EvalError::new(
+                    ErrorKind::Depth,
+                    format!("maximum recursion depth of {} exceeded", max_depth()),
+                ))
+            } else {
+                depth.set(depth.get() + 1);
+                Ok(DepthGuard)
+            }
+        })
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+// the macro that `form` calls, with the name it is called by, if `form` is
+// a macro call at all
+fn macro_for(env: &Env, form: &ast::SExpType) -> Option<(String, Rc<IType>)> {
+    let head = match form.get_exp().and_then(|items| items.first()) {
+        Some(head) if is_symbol(head) => head,
+        _ => return None,
+    };
+    let name = head.get_identifier_name().unwrap();
+    let value = env
+        .get(&head.binding_name().unwrap())
+        .or_else(|| env.get(&name))?;
+    match *value {
+        IType::Macro(_) | IType::SyntaxRules(_) => Some((name, Rc::clone(&value))),
+        _ => None,
+    }
+}
+
+// expands one macro call. A defmacro expander gets the argument forms
+// unevaluated, as data, and the data it returns is turned back into code
+fn expand_macro(name: &str, mac: &IType, form: &ast::SExpType) -> Result<ast::SExpType, EvalError> {
+    let expander = match *mac {
+        IType::Macro(ref expander) => expander,
+        IType::SyntaxRules(ref rules) => return rules.expand(form),
+        _ => unreachable!("macro_for only finds macros"),
+    };
+    let args = &form.get_exp().unwrap()[1..];
+    let arg_values: Vec<Rc<IType>> = args.iter().map(quoted_to_value).collect();
+    apply_named(name, expander, &arg_values)
+        .and_then(|expansion| value_to_sexp(&expansion))
+        .map_err(|e| {
+            e.called_from(Some(Frame {
+                function: name.to_string(),
+                args: arg_values,
+            }))
+        })
+}
+
+// expands the quoted macro call `form` once, or until what is left is no
+// longer a macro call. Anything else comes back unchanged
+fn macroexpand(env: &Env, form: &IType, repeat: bool) -> Result<Rc<IType>, EvalError> {
+    let mut form = value_to_sexp(form)?;
+    while let Some((name, mac)) = macro_for(env, &form) {
+        form = expand_macro(&name, &mac, &form)?;
+        if !repeat {
+            break;
+        }
+    }
+    Ok(quoted_to_value(&form))
+}
+
+// (define-syntax name (syntax-rules (literals) rules...)) binds a macro in
+// the global scope, like defmacro
+fn define_syntax(env: &Env, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() != 3 {
+        return bad_syntax("invalid number of arguments to define-syntax. should be (define-syntax name (syntax-rules ...))");
+    }
+    if !is_symbol(&n[1]) {
+        return Err(EvalError::new(
+            ErrorKind::Syntax,
+            format!("macro name {} not a symbol", n[1]),
+        ));
+    }
+    let mac = Rc::new(IType::SyntaxRules(SyntaxRules::parse(&n[2])?));
+    global_env(env).insert(n[1].binding_name().unwrap(), Rc::clone(&mac));
+    Ok(mac)
+}
+
+// (set! name value) changes the binding of name that is in scope, wherever
+// it was made, instead of making a new one like label does
+fn set_variable(env: &Env, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() != 3 {
+        return bad_syntax("invalid number of arguments to set!. should be (set! name value)");
+    }
+    if !is_symbol(&n[1]) {
+        return Err(EvalError::new(
+            ErrorKind::Syntax,
+            format!("variable name {} not a symbol", n[1]),
+        ));
+    }
+    let value = eval(env, &n[2])?;
+    // a name from a syntax-rules template may refer to a plain binding
+    let binding = n[1].binding_name().unwrap();
+    let plain = n[1].get_identifier_name().unwrap();
+    if env.set(&binding, Rc::clone(&value)).is_none()
+        && env.set(&plain, Rc::clone(&value)).is_none()
+    {
+        return Err(EvalError::new(
+            ErrorKind::Unbound,
+            format!("cannot set! undefined variable {}", n[1]),
+        ));
+    }
+    Ok(value)
+}
+
+// the scope the body of a let, let* or letrec form runs in. let evaluates
+// every value in the enclosing scope, let* each one in the scope the ones
+// before it made, and letrec all of them in the new scope itself, so they
+// can refer to each other
+fn let_scope(env: &Env, form: &str, n: &[ast::SExpType]) -> Result<Env, EvalError> {
+    if n.len() < 3 {
+        return Err(EvalError::new(
+            ErrorKind::Syntax,
+            format!(
+                "invalid number of arguments to {}. should be ({} ((name value)...) body...)",
+                form, form
+            ),
+        ));
+    }
+    let bindings = match n[1].get_exp() {
+        Some(bindings) => bindings,
+        None => {
+            return Err(EvalError::new(
+                ErrorKind::Syntax,
+                format!("{} bindings should be a list, got {}", form, n[1]),
+            ))
+        }
+    };
+    let mut names: Vec<String> = Vec::with_capacity(bindings.len());
+    for binding in bindings {
+        match binding.get_exp() {
+            Some(pair) if pair.len() == 2 && is_symbol(&pair[0]) => {
+                let name = pair[0].binding_name().unwrap();
+                if form != "let*" && names.contains(&name) {
+                    return Err(EvalError::new(
+                        ErrorKind::Syntax,
+                        format!("{} binds {} more than once", form, pair[0]),
+                    ));
                 }
-                "quote" => {
-                    if n.len() != 2 {
-                        Err("incorrect number of arguments to quote. should be (quote sexp)")
-                    } else {
-                        Ok(Rc::new(IType::QuotedList(n[1].clone())))
-                    }
-                } // end of quote interpretation
+                names.push(name);
+            }
+            _ => {
+                return Err(EvalError::new(
+                    ErrorKind::Syntax,
+                    format!(
+                        "{} binding should be of the form (name value), got {}",
+                        form, binding
+                    ),
+                ))
+            }
+        }
+    }
+    let values = bindings
+        .iter()
+        .map(|binding| &binding.get_exp().unwrap()[1]);
+    let mut scope = Environment::extend(env);
+    match form {
+        "let" => {
+            for (name, value) in names.into_iter().zip(values) {
+                let value = eval(env, value)?;
+                scope.insert(name, value);
+            }
+        }
+        "let*" => {
+            for (name, value) in names.into_iter().zip(values) {
+                let value = eval(&scope, value)?;
+                scope = Environment::extend(&scope);
+                scope.insert(name, value);
+            }
+        }
+        _ => {
+            let mut evaluated = Vec::with_capacity(names.len());
+            for value in values {
+                evaluated.push(eval(&scope, value)?);
+            }
+            for (name, value) in names.into_iter().zip(evaluated) {
+                scope.insert(name, value);
+            }
+        }
+    }
+    Ok(scope)
+}
 
-                "cons" => {
-                    if n.len() != 3 {
-                        Err("incorrect number of arguments to cons")
-                    } else {
-                        // figure out the type of item of item and the list
-                        let item = eval(env, &n[1]);
-                        match item {
-                            Ok(ref i) => {
-                                let list = eval(env, &n[2]);
-                                match list {
-                                    Ok(ref l) => {
-                                        match **l {
-                                            IType::List(ref list_arg) => {
-                                                let mut new_vec = Vec::new();
-                                                new_vec.push(Rc::clone(i));
-                                                new_vec.extend(list_arg.iter().cloned());
-                                                Ok(Rc::new(IType::List(new_vec)))
-                                            }
-                                            _ => Err("cannot cons item on a non-list"),
-                                        }
-                                        //Ok(Rc::new(IType::List(vec![Rc::clone(i), Rc::clone(l)])))
-                                    }
-                                    Err(k) => Err(k),
-                                }
-                            }
-                            Err(k) => Err(k),
-                        }
-                    }
-                } // end of cons interpretation
+// the error for a special form that was written the wrong way
+fn bad_syntax<T>(message: &str) -> Result<T, EvalError> {
+    Err(EvalError::new(ErrorKind::Syntax, message))
+}
 
-                "list" => {
-                    if n.len() < 2 {
-                        return Err("cannot make a list without arguments");
-                    } else {
-                        let mut m: Vec<Rc<IType>> = Vec::new();
-                        for i in &n[1..] {
-                            let item = eval(env, i);
-                            match item {
-                                Ok(g) => {
-                                    m.push(g);
-                                }
-                                Err(k) => {
-                                    return Err(k);
-                                }
-                            }
-                        }
-                        return Ok(Rc::new(IType::List(m)));
-                    }
+// where one step of eval got to
+enum Step<'a> {
+    Value(Rc<IType>),
+    /// evaluate this form in place of the one the step was given, in the
+    /// same scope
+    Tail(&'a ast::SExpType),
+    /// the same, in a new scope
+    Scoped(Env, &'a ast::SExpType),
+    /// the same, for a form made by expanding a macro
+    Expand(ast::SExpType),
+    /// call a function with arguments that are already evaluated, the name
+    /// is what the call site called it
+    Call(String, Rc<IType>, Vec<Rc<IType>>),
+}
+
+/// Evaluates `exp` in `env`. Errors say which expression failed and which
+/// Lisp function calls were in progress when it did.
+///
+/// Calls and `cond` branches in tail position run in constant stack, but
+/// every other level of nesting, like an argument, a predicate or a call
+/// whose value is still needed, recurses on the native stack. Past
+/// `max_depth()` levels eval fails with `ErrorKind::Depth` rather than
+/// overflowing. The default limit of 1000 needs up to 4MB of stack in a
+/// debug build and under 1MB in a release one, so it suits a main thread.
+/// A debug build on a thread spawned with the usual 2MB should lower it with
+/// `set_max_depth`, and deeper recursion needs it raised along with the
+/// stack size of the thread.
+pub fn eval(env: &Env, exp: &ast::SExpType) -> Result<Rc<IType>, EvalError> {
+    let _depth = DepthGuard::enter().map_err(|e| e.at(exp))?;
+    // a call or cond branch in tail position doesn't recurse: it replaces
+    // `exp` and `current_env` and goes round this loop again, so tail
+    // recursion runs in constant stack. `current_call` is the call whose body
+    // is running, a tail call takes its place in the backtrace
+    let mut current_env = CurrentScope(Rc::clone(env));
+    let mut current_body: Rc<ast::SExpType>;
+    let mut current_call: Option<Frame> = None;
+    let mut exp = exp;
+    loop {
+        let step = match eval_step(&current_env.0, exp) {
+            Ok(step) => step,
+            Err(e) => return Err(e.at(exp).called_from(current_call)),
+        };
+        match step {
+            Step::Value(value) => return Ok(value),
+            Step::Tail(next) => exp = next,
+            Step::Scoped(scope, next) => {
+                current_env = CurrentScope(scope);
+                exp = next;
+            }
+            Step::Expand(expansion) => {
+                current_body = Rc::new(expansion);
+                exp = &current_body;
+            }
+            Step::Call(name, func, args) => match enter_call(&name, &func, &args) {
+                Ok(Callee::Body(call_env, body)) => {
+                    current_env = CurrentScope(call_env);
+                    current_body = body;
+                    exp = &current_body;
+                    // the arguments stay with the call for the backtrace,
+                    // should its body fail
+                    current_call = Some(Frame {
+                        function: name,
+                        args: args,
+                    });
                 }
-                "car" => {
+                Ok(Callee::Value(value)) => return Ok(value),
+                Err(e) => return Err(e.at(exp).called_from(current_call)),
+            },
+        }
+    }
+}
+
+// the scope eval is running in, which may be the last hold on it once eval
+// moves on to another or returns
+struct CurrentScope(Env);
+
+impl Drop for CurrentScope {
+    fn drop(&mut self) {
+        release_scope(&self.0);
+    }
+}
+
+// true, false and nil evaluate to themselves, a variable to its value and
+// any other name that isn't a keyword to the atom of that name
+fn eval_identifier(env: &Env, name: &String) -> Result<Rc<IType>, EvalError> {
+    if is_keyword(name) {
+        match name.as_str() {
+            "true" => Ok(Rc::new(IType::True)),
+            "false" => Ok(Rc::new(IType::False)),
+            "nil" => Ok(Rc::new(IType::Nil)),
+            _ => Err(EvalError::new(
+                ErrorKind::Syntax,
+                format!("cannot evaluate the keyword {}", name),
+            )),
+        }
+    } else if let Some(val) = env.get(name) {
+        Ok(val)
+    } else if is_atom_type(name) {
+        Ok(Rc::new(IType::Atom(name.clone())))
+    } else {
+        Err(EvalError::new(
+            ErrorKind::Unbound,
+            format!("undefined variable {}", name),
+        ))
+    }
+}
+
+// evaluates `exp` as far as the next tail position or call
+fn eval_step<'a>(env: &Env, exp: &'a ast::SExpType) -> Result<Step<'a>, EvalError> {
+    // each form is evaluated by a function of its own, so that the frame of
+    // this one, which every level of nesting pays for, stays small
+    let value = match *exp {
+        ast::SExpType::Identifier(ref name) => eval_identifier(env, name),
+        // a name brought in by a syntax-rules template sees the bindings made
+        // by the same expansion first, then what the plain name means where
+        // the macro was defined. define-syntax binds globally, so that is the
+        // global scope rather than the one the macro is used in
+        ast::SExpType::Renamed(ref name, _) => match env.get(&exp.binding_name().unwrap()) {
+            Some(val) => Ok(val),
+            None => eval_identifier(&global_env(env), name),
+        },
+        // () is nil, the empty list
+        ast::SExpType::Exp(ref n) if n.is_empty() => Ok(Rc::new(IType::Nil)),
+        ast::SExpType::Exp(ref n) => {
+            // a macro call is replaced by its expansion before anything else
+            if let Some((name, mac)) = macro_for(env, exp) {
+                return Ok(Step::Expand(expand_macro(&name, &mac, exp)?));
+            }
+            // only a bare name picks a special form, ((lambda ...) x) is an application
+            let head = if n[0].is_identifier() {
+                get_first_term(&n[0])
+            } else {
+                String::new()
+            };
+            match head.as_ref() {
+                "atom" => eval_atom(env, n),
+                "quote" => {
                     if n.len() != 2 {
-                        return Err("invalid no. of arguments to car");
+                        bad_syntax("incorrect number of arguments to quote. should be (quote sexp)")
                     } else {
-                        let arg = eval(env, &n[1]);
-                        match arg {
-                            Ok(g) => match *g {
-                                IType::List(ref k) => {
-                                    if k.len() == 0 {
-                                        return Err("cannot car on empty list");
-                                    } else {
-                                        return Ok(Rc::clone(&k[0]));
-                                    }
-                                }
-                                _ => {
-                                    return Err("argument is not a list");
-                                }
-                            },
-                            Err(err_msg) => {
-                                return Err(err_msg);
-                            }
-                        }
+                        // quoted code is data like any other: lists, atoms
+                        // and numbers that the list primitives work on
+                        Ok(quoted_to_value(&n[1]))
                     }
                 }
-                "cdr" => {
+                "quasiquote" => {
                     if n.len() != 2 {
-                        return Err("invalid no. or arguments to cdr");
+                        bad_syntax("incorrect number of arguments to quasiquote. should be (quasiquote sexp)")
                     } else {
-                        let list = eval(env, &n[1]);
-                        match list {
-                            Ok(g) => match *g {
-                                IType::List(ref k) => {
-                                    if (*k).len() < 1 {
-                                        Err("cannot cdr on empty list")
-                                    } else if (*k).len() == 1 {
-                                        return Ok(Rc::new(IType::Nil));
-                                    } else {
-                                        // create a new copy of the list excluding
-                                        // the first item, stupid I know but ok
-                                        // for a hobby implementation
-                                        let new_k = &(*k)[1..];
-                                        return Ok(Rc::new(IType::List(new_k.to_vec())));
-                                    }
-                                }
-                                _ => {
-                                    return Err("cannot cdr on list");
-                                }
-                            },
-                            Err(err_msg) => {
-                                return Err(err_msg);
-                            }
-                        }
-                    }
-                }
-                "label" => {
-                    if n.len() != 3 {
-                        return Err("invalid number  of arguments passed to label");
-                    }
-                    if !is_symbol(&n[1]) {
-                        Err("variable name not a symbol")
-                    } else {
-                        let var = &n[1];
-                        let val = eval(env, &n[2]);
-                        match val {
-                            Ok(ref k) => {
-                                env.insert(var.to_string(), Rc::clone(k));
-                                Ok(Rc::clone(k))
-                            }
-                            Err(s) => Err(s),
-                        }
+                        quasiquote(env, &n[1], 1)
                     }
                 }
+                "unquote" | "unquote-splicing" => bad_syntax("unquote used outside of quasiquote"),
+                "equal" | "eq" => eval_equal(env, &head, n),
+                "cond" => return eval_cond(env, n),
+                "cons" => eval_cons(env, n),
+                "list" => eval_list(env, n),
+                "car" | "cdr" => eval_car_cdr(env, &head, n),
+                "label" => eval_label(env, n),
                 "lambda" => {
-                    if n.len() != 3 {
-                        return Err("invalid number of arguments to lambda. Expected 3");
+                    if n.len() < 3 {
+                        bad_syntax("invalid number of arguments to lambda. should be (lambda (args) body...)")
                     } else {
-                        let lambda_args = &n[1];
-                        if let ast::SExpType::Exp(args) = lambda_args {
-                            //iter over all arguments and ensure that each of them is an identifier
-                            for arg in args {
-                                if !ast::is_identifier(&arg) {
-                                    return Err(
-                                        "cannot have a non-identifier as a formal arg in lambda",
-                                    );
-                                } else {
-                                    continue;
-                                }
-                            }
-                        } else {
-                            return Err("lambda arguments must be a list");
-                        }
-                        let lambda_body = &n[2];
-                        if let ast::SExpType::Exp(_) = lambda_body {
-                            return Ok(Rc::new(IType::Function(
-                                Rc::new(lambda_args.clone()),
-                                Rc::new(lambda_body.clone()),
-                                lambda_args.len().unwrap(),
-                                RefCell::new(env.clone()),
-                            )));
-                        } else {
-                            return Err("lambda body must be a function");
-                        }
+                        make_function(&n[1], &n[2..], Rc::clone(env))
                     }
                 }
-                // it could be fn application
-                _ => {
-                    let func = eval(env, &n[0])?;
-                    match *func {
-                        IType::Function(
-                            ref formal_args_list,
-                            ref body,
-                            ref arity,
-                            ref captured_env,
-                        ) => {
-                            //evaluate the arguments
-                            if n.len() - 1 != *arity {
-                                return Err("incorrect no. of args to fn");
-                            }
-                            let formal_args = formal_args_list.get_exp().unwrap();
-                            let mut closure_env = captured_env.borrow_mut();
-                            for (idx, arg) in formal_args.iter().enumerate() {
-                                let evaluated_arg_value = eval(env, &n[idx + 1])?;
-                                //replace formal_args with arg from lambda application
-                                closure_env.insert(
-                                    arg.get_identifier_name().unwrap(),
-                                    evaluated_arg_value,
-                                );
-                            }
-                            let value = eval(&mut closure_env, body);
-                            return value;
-                        }
-                        _ => {
-                            return Err("cannot apply non-function");
-                        }
-                    }
+                "defun" => defun(env, n),
+                "defmacro" => defmacro(env, n),
+                "define-syntax" => define_syntax(env, n),
+                "set!" => set_variable(env, n),
+                "let" | "let*" | "letrec" => {
+                    // the body is in tail position, in the scope the bindings made
+                    let scope = let_scope(env, &head, n)?;
+                    let last = eval_sequence(&scope, &n[2..])?;
+                    return Ok(Step::Scoped(scope, last));
                 }
+                // (begin a b c) evaluates its forms in order, and is worth the
+                // last one, which is in tail position
+                "begin" if n.len() == 1 => Ok(Rc::new(IType::Nil)),
+                "begin" => return Ok(Step::Tail(eval_sequence(env, &n[1..])?)),
+                // it could be fn application
+                _ => return eval_application(env, head, n),
             }
         }
         ast::SExpType::Number(n) => Ok(Rc::new(IType::Number(n))),
+        ast::SExpType::Str(ref s) => Ok(Rc::new(IType::Str(s.clone()))),
+    };
+    value.map(Step::Value)
+}
+
+// everything but a pair is an atom, nil included
+fn eval_atom(env: &Env, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() != 2 {
+        return bad_syntax("incorrect no. of arguments to atom. should be (atom something)");
+    }
+    match *eval(env, &n[1])? {
+        IType::Pair(..) => Ok(to_bool(false)),
+        _ => Ok(to_bool(true)),
+    }
+}
+
+fn eval_equal(env: &Env, head: &str, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() != 3 {
+        return bad_syntax("incorrect number of arguments. should be (equal a b) or (eq a b)");
+    }
+    let a = eval(env, &n[1])?;
+    let b = eval(env, &n[2])?;
+    if head == "equal" {
+        Ok(to_bool(is_equal(&a, &b)))
+    } else {
+        Ok(to_bool(is_eq(&a, &b)))
+    }
+}
+
+// (cond (p1 e1) (p2 e2) ... (else e)), evaluates the e of the first p that
+// is truthy, in tail position
+fn eval_cond<'a>(env: &Env, n: &'a [ast::SExpType]) -> Result<Step<'a>, EvalError> {
+    for clause in &n[1..] {
+        let (predicate, body) = match clause.get_exp() {
+            Some(pair) if pair.len() == 2 => (&pair[0], &pair[1]),
+            _ => return bad_syntax("cond clause should be of the form (predicate expression)"),
+        };
+        let matched = match *predicate {
+            ast::SExpType::Identifier(ref name) | ast::SExpType::Renamed(ref name, _)
+                if name == "else" =>
+            {
+                true
+            }
+            _ => is_truthy(&*eval(env, predicate)?),
+        };
+        if matched {
+            return Ok(Step::Tail(body));
+        }
     }
+    Err(EvalError::new(ErrorKind::NoMatch, "no cond clause matched"))
+}
+
+fn eval_cons(env: &Env, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() != 3 {
+        return bad_syntax("incorrect number of arguments to cons");
+    }
+    // the new pair shares the list it is consed onto, and consing onto
+    // anything but a list makes a dotted pair
+    let i = eval(env, &n[1])?;
+    let l = eval(env, &n[2])?;
+    Ok(cons(i, l))
+}
+
+fn eval_list(env: &Env, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    let mut m: Vec<Rc<IType>> = Vec::new();
+    for i in &n[1..] {
+        m.push(eval(env, i)?);
+    }
+    Ok(list_from(m))
+}
+
+fn eval_car_cdr(env: &Env, head: &str, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() != 2 {
+        return Err(EvalError::new(
+            ErrorKind::Syntax,
+            format!("invalid no. of arguments to {}", head),
+        ));
+    }
+    let arg = eval(env, &n[1])?;
+    match *arg {
+        IType::Pair(ref car, ref cdr) => {
+            let half = if head == "car" { car } else { cdr };
+            Ok(Rc::clone(&half.borrow()))
+        }
+        IType::Nil => Err(EvalError::new(
+            ErrorKind::Type,
+            format!("cannot {} on empty list", head),
+        )),
+        _ => Err(EvalError::new(
+            ErrorKind::Type,
+            format!("{} expects a list, got {}", head, arg),
+        )),
+    }
+}
+
+fn eval_label(env: &Env, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() != 3 {
+        return bad_syntax("invalid number  of arguments passed to label");
+    }
+    if !is_symbol(&n[1]) {
+        return Err(EvalError::new(
+            ErrorKind::Syntax,
+            format!("variable name {} not a symbol", n[1]),
+        ));
+    }
+    let k = eval(env, &n[2])?;
+    env.insert(n[1].binding_name().unwrap(), Rc::clone(&k));
+    Ok(k)
+}
+
+// (defun name (args) body...) binds a function in the global scope, wherever
+// it is evaluated
+fn defun(env: &Env, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() < 4 {
+        return bad_syntax(
+            "invalid number of arguments to defun. should be (defun name (args) body...)",
+        );
+    }
+    if !is_symbol(&n[1]) {
+        return Err(EvalError::new(
+            ErrorKind::Syntax,
+            format!("function name {} not a symbol", n[1]),
+        ));
+    }
+    let function = make_function(&n[2], &n[3..], Rc::clone(env))?;
+    global_env(env).insert(n[1].binding_name().unwrap(), Rc::clone(&function));
+    Ok(function)
+}
+
+// (defmacro name (args) body...) is defun for a macro: a call (name a b) runs
+// body with the forms a and b as data, and what it returns is evaluated in
+// place of the call
+fn defmacro(env: &Env, n: &[ast::SExpType]) -> Result<Rc<IType>, EvalError> {
+    if n.len() < 4 {
+        return bad_syntax(
+            "invalid number of arguments to defmacro. should be (defmacro name (args) body...)",
+        );
+    }
+    if !is_symbol(&n[1]) {
+        return Err(EvalError::new(
+            ErrorKind::Syntax,
+            format!("macro name {} not a symbol", n[1]),
+        ));
+    }
+    let expander = make_function(&n[2], &n[3..], Rc::clone(env))?;
+    let mac = Rc::new(IType::Macro(expander));
+    global_env(env).insert(n[1].binding_name().unwrap(), Rc::clone(&mac));
+    Ok(mac)
+}
+
+// every argument is evaluated in the caller's scope before the call gets a
+// scope of its own
+fn eval_application<'a>(
+    env: &Env,
+    head: String,
+    n: &'a [ast::SExpType],
+) -> Result<Step<'a>, EvalError> {
+    let func = eval(env, &n[0])?;
+    let mut args = Vec::with_capacity(n.len() - 1);
+    for arg in &n[1..] {
+        args.push(eval(env, arg)?);
+    }
+    let name = if head.is_empty() {
+        "lambda".to_string()
+    } else {
+        head
+    };
+    Ok(Step::Call(name, func, args))
 }
@@ -1,16 +1,20 @@
 #[macro_use]
 extern crate lazy_static;
 
+#[macro_use]
+mod depth;
+
 pub mod ast;
 pub mod env;
 mod realtests;
+pub mod syntax_rules;
 pub mod tokenizer;
 #[cfg(test)]
 mod tests {
     #[test]
     fn it_works() {
         use super::tokenizer;
-        let g = tokenizer::parse_string(&"(+ ab)".to_string());
+        let g = tokenizer::parse_string(&"(+ ab)".to_string()).unwrap();
         assert_eq!(
             vec![
                 tokenizer::TokenType::o_brace,
@@ -27,7 +31,7 @@ mod tests {
         use super::ast;
         use super::env;
         use super::tokenizer;;
-        let g = tokenizer::parse_string(&"(+ a)".to_string());
+        let g = tokenizer::parse_string(&"(+ a)".to_string()).unwrap();
         let mut start_i: usize = 0;
         let m = ast::stream_to_ast(&g);
         assert_eq!(m.is_ok(), true);
@@ -40,14 +44,14 @@ mod tests {
             ast_vec
         );
         assert_eq!(ast_vec.to_string(), "( + a)");
-        let p = tokenizer::parse_string(&"a".to_string());
+        let p = tokenizer::parse_string(&"a".to_string()).unwrap();
         assert_eq!(p.len() != 0, true);
         let q = ast::stream_to_ast(&p);
         assert_eq!(q.is_ok(), true);
         let ast_vec2 = q.unwrap();
         assert_eq!(ast::SExpType::Identifier("a".to_string()), ast_vec2);
 
-        let token_vec3 = tokenizer::parse_string(&"(+ a) (+ c d)".to_string());
+        let token_vec3 = tokenizer::parse_string(&"(+ a) (+ c d)".to_string()).unwrap();
         let ast_vec3 = ast::stream_to_ast(&token_vec3);
         let av3 = ast_vec3.unwrap();
         assert_eq!(
@@ -57,7 +61,7 @@ mod tests {
             ]),
             av3
         );
-        let token_vec4 = tokenizer::parse_string(&"(lambda (a) (car a))".to_string());
+        let token_vec4 = tokenizer::parse_string(&"(lambda (a) (car a))".to_string()).unwrap();
         let ast_vec4 = ast::stream_to_ast(&token_vec4);
         let av4 = ast_vec4.unwrap();
         let lambda_str = "lambda".to_string();
@@ -78,19 +82,291 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_program() {
+        use super::ast;
+        use super::tokenizer;
+        let tokens = tokenizer::parse_string(&"(+ a) (+ c d) e 12".to_string()).unwrap();
+        let forms = ast::parse_program(&tokens);
+        assert_eq!(
+            Ok(vec![
+                ast::SExpType::Exp(vec![
+                    ast::SExpType::Identifier("+".to_string()),
+                    ast::SExpType::Identifier("a".to_string()),
+                ]),
+                ast::SExpType::Exp(vec![
+                    ast::SExpType::Identifier("+".to_string()),
+                    ast::SExpType::Identifier("c".to_string()),
+                    ast::SExpType::Identifier("d".to_string()),
+                ]),
+                ast::SExpType::Identifier("e".to_string()),
+                ast::SExpType::Number(12),
+            ]),
+            forms
+        );
+        let mut lazy_forms = ast::program_iter(&tokens);
+        assert_eq!(
+            lazy_forms.next().map(|f| f.unwrap().to_string()),
+            Some("( + a)".to_string())
+        );
+        assert_eq!(lazy_forms.count(), 3);
+        let empty = tokenizer::parse_string(&"".to_string()).unwrap();
+        assert_eq!(ast::parse_program(&empty), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        use super::ast::{self, ParseError};
+        use super::tokenizer;
+        let parse =
+            |src: &str| ast::parse_program(&tokenizer::parse_string(&src.to_string()).unwrap());
+        assert_eq!(
+            parse("(a) )"),
+            Err(ParseError::UnexpectedClose {
+                index: 3,
+                span: Default::default(),
+            })
+        );
+        assert_eq!(
+            parse("(a (b c)"),
+            Err(ParseError::UnterminatedList {
+                index: 0,
+                span: Default::default(),
+            })
+        );
+        let empty = tokenizer::parse_string(&"".to_string()).unwrap();
+        assert_eq!(ast::stream_to_ast(&empty), Err(ParseError::EmptyInput));
+        let mut index = 1;
+        let tokens = tokenizer::parse_string(&"a".to_string()).unwrap();
+        assert_eq!(
+            ast::make_ast(&tokens, &mut index),
+            Err(ParseError::EndOfStream {
+                index: 1,
+                span: Default::default(),
+            })
+        );
+        // a bad form stops the lazy parse instead of looping or panicking
+        let bad = tokenizer::parse_string(&"a ) b".to_string()).unwrap();
+        let forms: Vec<_> = ast::program_iter(&bad).collect();
+        assert_eq!(forms.len(), 2);
+        assert_eq!(
+            forms[1],
+            Err(ParseError::UnexpectedClose {
+                index: 1,
+                span: Default::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        use super::ast;
+        use super::tokenizer::{self, Span};
+        let source = "(car\n  (list ab c))";
+        let (tokens, spans) = tokenizer::tokenize(source).unwrap();
+        assert_eq!(tokens.len(), spans.len());
+        assert_eq!(
+            spans[4],
+            Span {
+                start: 13,
+                end: 15,
+                line: 2,
+                column: 9,
+            }
+        );
+        let forms = ast::parse_spanned_program(source).unwrap();
+        assert_eq!(forms.len(), 1);
+        let inner = &forms[0].spans.children[1];
+        assert_eq!((inner.span.start, inner.span.end), (7, 18));
+        assert_eq!(inner.children.len(), 3);
+        assert_eq!(
+            ast::describe_at(
+                "test.mml",
+                source,
+                &inner.children[1].span,
+                "undefined value"
+            ),
+            "test.mml:2:9: undefined value\n  (list ab c))\n        ^^"
+        );
+
+        let err = ast::parse_spanned_program("(a)\n  )").unwrap_err();
+        assert_eq!(
+            err.span(),
+            Some(Span {
+                start: 6,
+                end: 7,
+                line: 2,
+                column: 3,
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "found a closing brace without an opening brace at line 2, column 3"
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        use super::ast::{self, ParseError};
+        use super::tokenizer::{self, TokenType};
+        let tokens =
+            tokenizer::parse_string(&r#"(f "hello world" "a\"b\\c\n\t\u{1F600}")"#.to_string());
+        assert_eq!(
+            tokens,
+            Ok(vec![
+                TokenType::o_brace,
+                TokenType::Identifier("f".to_string()),
+                TokenType::Str("hello world".to_string()),
+                TokenType::Str("a\"b\\c\n\t\u{1F600}".to_string()),
+                TokenType::c_brace,
+            ])
+        );
+        let form = ast::stream_to_ast(&tokens.unwrap()).unwrap();
+        assert_eq!(
+            form.to_string(),
+            "( f \"hello world\" \"a\\\"b\\\\c\\n\\t\u{1F600}\")"
+        );
+        let (_, spans) = tokenizer::tokenize(r#"x "a b" y"#).unwrap();
+        assert_eq!((spans[1].start, spans[1].end, spans[2].column), (2, 7, 9));
+
+        let unterminated = ast::parse_spanned_program("(f\n  \"abc)").unwrap_err();
+        match unterminated {
+            ParseError::UnterminatedString { index, span } => {
+                assert_eq!(index, 2);
+                assert_eq!((span.line, span.column), (2, 3));
+            }
+            other => panic!("expected an unterminated string, got {:?}", other),
+        }
+        match tokenizer::parse_string(&r#""bad \q""#.to_string()) {
+            Err(ParseError::InvalidEscape { span, .. }) => assert_eq!(span.column, 6),
+            other => panic!("expected an invalid escape, got {:?}", other),
+        }
+        assert!(tokenizer::parse_string(&r#""\u{110000}""#.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_comments() {
+        use super::ast::{self, ParseError};
+        use super::tokenizer::{self, TokenType};
+        let source = "; a whole line\n(f a ; trailing\n #| block #| nested |# still |# b)";
+        assert_eq!(
+            tokenizer::parse_string(&source.to_string()),
+            Ok(vec![
+                TokenType::o_brace,
+                TokenType::Identifier("f".to_string()),
+                TokenType::Identifier("a".to_string()),
+                TokenType::Identifier("b".to_string()),
+                TokenType::c_brace,
+            ])
+        );
+        let parsed = ast::parse_spanned_program("#;(skipped (form)) (f #; a b #;c) #;d").unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].exp.to_string(), "( f b)");
+        assert_eq!(parsed[0].spans.children.len(), 2);
+        assert_eq!(parsed[0].spans.children[1].span.column, 28);
+        let string_with_semicolon = ast::parse_spanned_program("\"a ; b\"").unwrap();
+        assert_eq!(
+            string_with_semicolon[0].exp,
+            ast::SExpType::Str("a ; b".to_string())
+        );
+        match ast::parse_spanned_program("(a #| never closed") {
+            Err(ParseError::UnterminatedComment { span, .. }) => assert_eq!(span.column, 4),
+            other => panic!("expected an unterminated comment, got {:?}", other),
+        }
+        match ast::parse_spanned_program("(a #;)") {
+            Err(ParseError::UnexpectedClose { span, .. }) => assert_eq!(span.column, 6),
+            other => panic!("expected an unexpected close, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_quote_shorthand() {
+        use super::ast;
+        use super::tokenizer::{self, TokenType};
+        assert_eq!(
+            tokenizer::parse_string(&"'a `(b ,c ,@d)".to_string()),
+            Ok(vec![
+                TokenType::Quote,
+                TokenType::Identifier("a".to_string()),
+                TokenType::Quasiquote,
+                TokenType::o_brace,
+                TokenType::Identifier("b".to_string()),
+                TokenType::Unquote,
+                TokenType::Identifier("c".to_string()),
+                TokenType::UnquoteSplicing,
+                TokenType::Identifier("d".to_string()),
+                TokenType::c_brace,
+            ])
+        );
+        let forms = ast::parse_spanned_program("'(a 'b) `(x ,y ,@z)").unwrap();
+        assert_eq!(forms[0].exp.to_string(), "( quote ( a ( quote b)))");
+        assert_eq!(
+            forms[1].exp.to_string(),
+            "( quasiquote ( x ( unquote y) ( unquote-splicing z)))"
+        );
+        assert_eq!((forms[0].spans.span.start, forms[0].spans.span.end), (0, 7));
+        assert_eq!(forms[0].spans.children[1].children[1].span.start, 4);
+        assert!(ast::parse_spanned_program("(a ')").is_err());
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        use super::ast::{self, ParseError};
+        // far deeper than the limit, this is a clean error rather than a crash
+        let deep = format!("{}{}", "(".repeat(100_000), ")".repeat(100_000));
+        match ast::parse_spanned_program(&deep) {
+            Err(ParseError::TooDeep { index, span }) => {
+                assert_eq!(index, ast::DEFAULT_MAX_DEPTH);
+                assert_eq!(span.column, ast::DEFAULT_MAX_DEPTH + 1);
+            }
+            other => panic!("expected too deep, got {:?}", other.map(|f| f.len())),
+        }
+        let quotes = format!("{}a", "'".repeat(100_000));
+        match ast::parse_spanned_program(&quotes) {
+            Err(ParseError::TooDeep { .. }) => {}
+            other => panic!("expected too deep, got {:?}", other.map(|f| f.len())),
+        }
+        // right up to the limit is fine
+        let at_limit = format!(
+            "{}x{}",
+            "(".repeat(ast::DEFAULT_MAX_DEPTH),
+            ")".repeat(ast::DEFAULT_MAX_DEPTH)
+        );
+        assert!(ast::parse_spanned_program(&at_limit).is_ok());
+        // the limit is per thread and can be changed
+        assert_eq!(ast::set_max_depth(3), ast::DEFAULT_MAX_DEPTH);
+        assert!(ast::parse_spanned_program("(a (b (c)))").is_ok());
+        assert!(ast::parse_spanned_program("(a (b '(c)))").is_err());
+        assert!(ast::parse_spanned_program("(a (b (c)) (d (e)))").is_ok());
+        ast::set_max_depth(ast::DEFAULT_MAX_DEPTH);
+        // unbalanced input still reports the innermost unclosed brace
+        match ast::parse_spanned_program("(a (b (c)") {
+            Err(ParseError::UnterminatedList { index, .. }) => assert_eq!(index, 2),
+            other => panic!("expected an unterminated list, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_env_from_lib() {
         use super::ast;
         use super::env;
         use super::tokenizer;
-        let label_form = tokenizer::parse_string(&"(label a nil)".to_string());
+        let label_form = tokenizer::parse_string(&"(label a nil)".to_string()).unwrap();
         let m = ast::stream_to_ast(&label_form);
         assert_eq!(m.is_ok(), true);
-        let empty_form = tokenizer::parse_string(&"".to_string());
+        let empty_form = tokenizer::parse_string(&"".to_string()).unwrap();
         let empty_m = ast::stream_to_ast(&empty_form);
         assert_eq!(empty_m.is_err(), true);
-        let label_bad_form = tokenizer::parse_string(&"(label a ())".to_string());
-        let mb = ast::stream_to_ast(&label_bad_form);
-        assert_eq!(mb.is_err(), true);
+        let label_nil_form = tokenizer::parse_string(&"(label a ())".to_string()).unwrap();
+        let mb = ast::stream_to_ast(&label_nil_form);
+        assert_eq!(
+            mb,
+            Ok(ast::SExpType::Exp(vec![
+                ast::SExpType::Identifier("label".to_string()),
+                ast::SExpType::Identifier("a".to_string()),
+                ast::SExpType::Exp(vec![]),
+            ]))
+        );
+        assert_eq!(mb.unwrap().to_string(), "( label a ())");
     }
 }
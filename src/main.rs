@@ -1,10 +1,80 @@
 extern crate mml;
 
-use mml::{ast, env, tokenizer};
+use mml::{ast, env};
+use std::fs;
 use std::io;
+use std::thread;
+
+// how many calls of an error's backtrace to print
+const MAX_FRAMES: usize = 10;
+
+// evals every form in `source`, printing each value. Parse and eval errors
+// are reported against `name` with the offending line underlined.
+fn run_source(lisp_env: &env::Env, name: &str, source: &str) {
+    let forms = match ast::parse_spanned_program(source) {
+        Ok(forms) => forms,
+        Err(e) => {
+            let span = e.span().unwrap_or_default();
+            println!("{}", ast::describe_at(name, source, &span, &e.to_string()));
+            return;
+        }
+    };
+    for form in forms {
+        match env::eval(lisp_env, &form.exp) {
+            Ok(k) => println!("{}", k),
+            Err(e) => {
+                // point at the part of the form that failed. With calls in
+                // the backtrace it failed in some function's body, which may
+                // not be in this form at all, and a part of the form that
+                // only looks the same would be the wrong place to point at
+                let located = if e.backtrace().is_empty() {
+                    e.expr().and_then(|expr| form.span_of(expr))
+                } else {
+                    None
+                };
+                let span = located.unwrap_or(form.spans.span);
+                println!("{}", ast::describe_at(name, source, &span, &e.to_string()));
+                if let (None, Some(expr)) = (located, e.expr()) {
+                    println!("  while evaluating {}", expr);
+                }
+                // deep recursion leaves thousands of frames, the innermost
+                // ones are where to look
+                let backtrace = e.backtrace();
+                for frame in backtrace.iter().take(MAX_FRAMES) {
+                    println!("  in {}", frame);
+                }
+                if backtrace.len() > MAX_FRAMES {
+                    println!("  ... {} more", backtrace.len() - MAX_FRAMES);
+                }
+                return;
+            }
+        }
+    }
+}
+
+// the parser's and evaluator's default depth limits are sized for an 8MB
+// stack, run the interpreter on a thread with this many times that and
+// raise the limits to match
+const STACK_MULTIPLE: usize = 32;
 
 fn main() -> io::Result<()> {
-    let mut lisp_env = env::make_env();
+    let interpreter = thread::Builder::new()
+        .stack_size(STACK_MULTIPLE * 8 * 1024 * 1024)
+        .spawn(|| {
+            ast::set_max_depth(ast::DEFAULT_MAX_DEPTH * STACK_MULTIPLE);
+            env::set_max_depth(env::DEFAULT_MAX_DEPTH * STACK_MULTIPLE);
+            run()
+        })?;
+    interpreter.join().expect("interpreter thread panicked")
+}
+
+fn run() -> io::Result<()> {
+    let lisp_env = env::make_env();
+    if let Some(path) = std::env::args().nth(1) {
+        let source = fs::read_to_string(&path)?;
+        run_source(&lisp_env, &path, &source);
+        return Ok(());
+    }
     loop {
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
@@ -12,15 +82,7 @@ fn main() -> io::Result<()> {
             println!("quitting the program");
             break;
         } else {
-            let tok_stream = tokenizer::parse_string(&input);
-            let ast = ast::stream_to_ast(&tok_stream);
-            match ast {
-                Ok(valid_ast) => match env::eval(&mut lisp_env, &valid_ast) {
-                    Ok(k) => println!("{}", k),
-                    Err(s) => println!("{}", s),
-                },
-                Err(g) => println!("{}", g),
-            }
+            run_source(&lisp_env, "<stdin>", &input);
         }
     }
 
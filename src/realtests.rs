@@ -2,38 +2,41 @@
 mod realtests {
     use ast;
     use env;
-    use env::IType;
-    use std::collections::HashMap;
+    use env::{ErrorKind, IType};
     use std::rc::Rc;
+    use std::thread;
     use tokenizer;
 
-    fn str_to_eval(
-        k: &'static str,
-        env: &mut HashMap<String, Rc<IType>>,
-    ) -> Result<Rc<IType>, &'static str> {
-        let tok_stream = tokenizer::parse_string(&k.to_string());
+    fn str_to_eval(k: &'static str, env: &env::Env) -> Result<Rc<IType>, env::EvalError> {
+        let tok_stream = tokenizer::parse_string(&k.to_string()).unwrap();
         let ast = ast::stream_to_ast(&tok_stream).unwrap();
         return env::eval(env, &ast);
     }
 
+    // the kind and message of the error that evaluating `k` fails with
+    fn error_of(k: &'static str, env: &env::Env) -> (ErrorKind, String) {
+        let e = str_to_eval(k, env).unwrap_err();
+        (e.kind(), e.message().to_string())
+    }
+
     #[test]
     fn test_env() {
-        let mut env = env::make_env();
-        let t = str_to_eval("true", &mut env);
+        let env = env::make_env();
+        let t = str_to_eval("true", &env);
         assert_eq!(*(t.unwrap()), IType::True);
         env.insert(
             "a".to_string(),
             Rc::new(env::IType::Atom(":hohoho".to_string())),
         );
         {
-            let val = str_to_eval("a", &mut env);
+            let val = str_to_eval("a", &env);
             assert_eq!(val.is_ok(), true);
             assert_eq!(*(val.unwrap()), env::IType::Atom(":hohoho".to_string()));
         }
 
-        let val = str_to_eval("(list a b c)", &mut env);
+        let val = str_to_eval("(list a b c)", &env);
         assert_eq!(val.is_ok(), true);
-        let listdefine = str_to_eval("(label k (list a b c))", &mut env);
+        let listdefine = str_to_eval("(label k (list a b c))", &env);
         assert_eq!(
             listdefine.is_ok(),
             true,
@@ -41,26 +44,26 @@ mod realtests {
             listdefine
         );
         // lets see if we can do a car on k
-        let cark = str_to_eval("(car k)", &mut env);
+        let cark = str_to_eval("(car k)", &env);
         assert_eq!(cark.is_ok(), true, "car failed due to {:?}", cark);
         assert_eq!(*(cark.unwrap()), env::IType::Atom(":hohoho".to_string()));
-        let tok_stream2 = tokenizer::parse_string(&":a".to_string());
+        let tok_stream2 = tokenizer::parse_string(&":a".to_string()).unwrap();
         let ast2 = ast::stream_to_ast(&tok_stream2).unwrap();
-        let val2 = env::eval(&mut env, &ast2);
+        let val2 = env::eval(&env, &ast2);
         assert_eq!(true, env::is_atom(&String::from(":a")));
         assert_eq!(true, val2.is_ok());
         assert_eq!(true, env::truthy(&String::from("True")));
         assert_eq!(true, env::truthy(&String::from("False")));
         assert_eq!(false, env::truthy(&String::from("Frue")));
-        let tok_stream_3 = tokenizer::parse_string(&"(label a :b)".to_string());
+        let tok_stream_3 = tokenizer::parse_string(&"(label a :b)".to_string()).unwrap();
         let ast3 = ast::stream_to_ast(&tok_stream_3).unwrap();
-        let v = env::eval(&mut env, &ast3);
+        let v = env::eval(&env, &ast3);
         assert_eq!(v.is_ok(), true);
         let ret_val = v.ok();
         assert_eq!(*ret_val.unwrap(), env::IType::Atom(":b".to_string()));
-        let tok_stream_4 = tokenizer::parse_string(&"a".to_string());
+        let tok_stream_4 = tokenizer::parse_string(&"a".to_string()).unwrap();
         let ast4 = ast::stream_to_ast(&tok_stream_4).unwrap();
-        let v = env::eval(&mut env, &ast4);
+        let v = env::eval(&env, &ast4);
         assert_eq!(
             true,
             match v {
@@ -71,7 +74,7 @@ mod realtests {
                 Err(g) => false,
             }
         );
-        let lambda_retval = str_to_eval("(lambda (a) (car a))", &mut env);
+        let lambda_retval = str_to_eval("(lambda (a) (car a))", &env);
         assert_eq!(
             true,
             lambda_retval.is_ok(),
@@ -79,5 +82,1121 @@ mod realtests {
             lambda_retval
         );
     }
+    #[test]
+    fn test_strings() {
+        let env = env::make_env();
+        let s = str_to_eval(r#""tab\there""#, &env).unwrap();
+        assert_eq!(*s, IType::Str("tab\there".to_string()));
+        assert_eq!(s.to_string(), r#""tab\there""#);
+        let first = str_to_eval(r#"(car (list "a b" :c))"#, &env).unwrap();
+        assert_eq!(*first, IType::Str("a b".to_string()));
+        let listed = str_to_eval(r#"(list "say \"hi\"" :c)"#, &env).unwrap();
+        assert_eq!(listed.to_string(), r#"( "say \"hi\"" :c)"#);
+    }
+    #[test]
+    fn test_quasiquote() {
+        let env = env::make_env();
+        str_to_eval("(label xs (list 1 2))", &env).unwrap();
+        let plain = str_to_eval("`(a b)", &env).unwrap();
+        assert_eq!(plain.to_string(), "( a b)");
+        let spliced = str_to_eval("`(a ,xs ,@xs (c ,(car xs)))", &env).unwrap();
+        assert_eq!(spliced.to_string(), "( a ( 1 2) 1 2 ( c 1))");
+        let quoted_splice = str_to_eval("`(,@'(p q) r)", &env).unwrap();
+        assert_eq!(quoted_splice.to_string(), "( p q r)");
+        let nested = str_to_eval("`(a `(b ,(c ,(car xs))))", &env).unwrap();
+        assert_eq!(
+            nested.to_string(),
+            "( a ( quasiquote ( b ( unquote ( c 1)))))"
+        );
+        let dotted = str_to_eval("`(1 . ,(+ 1 1))", &env).unwrap();
+        assert_eq!(dotted.to_string(), "( 1 . 2)");
+        assert_eq!(
+            *str_to_eval("(cdr `(1 . ,(+ 1 1)))", &env).unwrap(),
+            IType::Number(2)
+        );
+        let dotted_list = str_to_eval("`(,@xs . ,xs)", &env).unwrap();
+        assert_eq!(dotted_list.to_string(), "( 1 2 1 2)");
+        assert!(str_to_eval("`(a ,@(car xs))", &env).is_err());
+        assert!(str_to_eval(",xs", &env).is_err());
+    }
+    #[test]
+    fn test_empty_list() {
+        let env = env::make_env();
+        assert_eq!(*str_to_eval("()", &env).unwrap(), IType::Nil);
+        assert_eq!(*str_to_eval("'()", &env).unwrap(), IType::Nil);
+        assert_eq!(*str_to_eval("(label a ())", &env).unwrap(), IType::Nil);
+        assert_eq!(*str_to_eval("(list)", &env).unwrap(), IType::Nil);
+        let one = str_to_eval("(cons 1 ())", &env).unwrap();
+        assert_eq!(*one, *env::list_from(vec![Rc::new(IType::Number(1))]));
+        str_to_eval("(label xs (cons 1 (cons 2 a)))", &env).unwrap();
+        assert_eq!(str_to_eval("xs", &env).unwrap().to_string(), "( 1 2)");
+        assert_eq!(*str_to_eval("(cdr (cdr xs))", &env).unwrap(), IType::Nil);
+        assert!(str_to_eval("(car ())", &env).is_err());
+        assert!(str_to_eval("(cdr ())", &env).is_err());
+        assert_eq!(*str_to_eval("(atom ())", &env).unwrap(), IType::True);
+        // atom looks at the value of its argument
+        assert_eq!(*str_to_eval("(atom 'a)", &env).unwrap(), IType::True);
+        assert_eq!(*str_to_eval("(atom (quote a))", &env).unwrap(), IType::True);
+        assert_eq!(*str_to_eval("(atom (car xs))", &env).unwrap(), IType::True);
+        assert_eq!(*str_to_eval("(atom xs)", &env).unwrap(), IType::False);
+        assert_eq!(*str_to_eval("(atom '(1 2))", &env).unwrap(), IType::False);
+        assert_eq!(
+            str_to_eval("`(a () ,@())", &env).unwrap().to_string(),
+            "( a Nil)"
+        );
+    }
+    #[test]
+    fn test_cond() {
+        let env = env::make_env();
+        str_to_eval("(label x :b)", &env).unwrap();
+        let picked = str_to_eval("(cond (false :a) (nil :b) (x :c) (else :d))", &env);
+        assert_eq!(*picked.unwrap(), IType::Atom(":c".to_string()));
+        // the empty list is nil, so it is false too
+        let fallback = str_to_eval("(cond (() :a) (else (list x)))", &env);
+        assert_eq!(fallback.unwrap().to_string(), "( :b)");
+        // only the chosen branch is evaluated
+        let lazy = str_to_eval("(cond (true 1) (undefined-thing 2))", &env);
+        assert_eq!(*lazy.unwrap(), IType::Number(1));
+        assert_eq!(
+            error_of("(cond (false 1) (nil 2))", &env),
+            (ErrorKind::NoMatch, "no cond clause matched".to_string())
+        );
+        assert!(str_to_eval("(cond (true))", &env).is_err());
+        assert!(str_to_eval("(cond (undefined-thing 1))", &env).is_err());
+        assert_eq!(env::is_truthy(&IType::Number(0)), true);
+        assert_eq!(env::is_truthy(&IType::False), false);
+    }
+    #[test]
+    fn test_equal_and_eq() {
+        let env = env::make_env();
+        str_to_eval("(label xs (list 1 :a \"s\"))", &env).unwrap();
+        str_to_eval("(label ys (list 1 :a \"s\"))", &env).unwrap();
+        str_to_eval("(label f (lambda (a) (car a)))", &env).unwrap();
+        let check = |src: &'static str, expected: IType, env: &_| {
+            assert_eq!(*str_to_eval(src, env).unwrap(), expected, "{}", src);
+        };
+        check("(equal 1 1)", IType::True, &env);
+        check("(equal 1 2)", IType::False, &env);
+        check("(equal :a :a)", IType::True, &env);
+        check("(equal xs ys)", IType::True, &env);
+        check("(equal xs (cdr ys))", IType::False, &env);
+        check("(equal '(1 (a b)) (list 1 '(a b)))", IType::True, &env);
+        check("(equal '(1 2) '(1 2 3))", IType::False, &env);
+        check("(equal nil ())", IType::True, &env);
+        check("(equal f f)", IType::True, &env);
+        check("(equal f (lambda (a) (car a)))", IType::False, &env);
+        check("(eq xs xs)", IType::True, &env);
+        check("(eq xs ys)", IType::False, &env);
+        check("(eq nil ())", IType::True, &env);
+        check("(eq (car xs) (car xs))", IType::True, &env);
+        // atoms with the same name are the same atom
+        check("(eq 'a 'a)", IType::True, &env);
+        check("(eq (car '(a b)) 'a)", IType::True, &env);
+        check("(eq :a (car (cdr xs)))", IType::True, &env);
+        check("(eq 'a 'b)", IType::False, &env);
+        assert!(str_to_eval("(equal 1)", &env).is_err());
+    }
+    #[test]
+    fn test_defun() {
+        let env = env::make_env();
+        str_to_eval(
+            "(defun last (xs) (cond ((equal (cdr xs) nil) (car xs)) (else (last (cdr xs)))))",
+            &env,
+        )
+        .unwrap();
+        let last = str_to_eval("(last (list :a :b :c))", &env);
+        assert_eq!(*last.unwrap(), IType::Atom(":c".to_string()));
+        // is-odd calls is-even, which is only defined afterwards
+        str_to_eval(
+            "(defun is-odd (xs) (cond ((equal xs nil) false) (else (is-even (cdr xs)))))",
+            &env,
+        )
+        .unwrap();
+        str_to_eval(
+            "(defun is-even (xs) (cond ((equal xs nil) true) (else (is-odd (cdr xs)))))",
+            &env,
+        )
+        .unwrap();
+        let odd = str_to_eval("(is-odd (list 1 2 3))", &env);
+        assert_eq!(*odd.unwrap(), IType::True);
+        // redefining replaces the binding, callers pick up the new one
+        str_to_eval("(defun pick (xs) (car xs))", &env).unwrap();
+        str_to_eval("(defun use-pick (xs) (pick xs))", &env).unwrap();
+        str_to_eval("(defun pick (xs) (cdr xs))", &env).unwrap();
+        let picked = str_to_eval("(use-pick (list 1 2))", &env);
+        assert_eq!(picked.unwrap().to_string(), "( 2)");
+        assert!(str_to_eval("(defun car (xs) xs)", &env).is_err());
+        assert!(str_to_eval("(defun f xs (car xs))", &env).is_err());
+    }
+    #[test]
+    fn test_arithmetic() {
+        let env = env::make_env();
+        let check = |src: &'static str, expected: IType, env: &_| {
+            assert_eq!(*str_to_eval(src, env).unwrap(), expected, "{}", src);
+        };
+        check("(+)", IType::Number(0), &env);
+        check("(+ 1 2 3)", IType::Number(6), &env);
+        check("(* 2 3 4)", IType::Number(24), &env);
+        check("(- 5)", IType::Number(-5), &env);
+        check("(- 10 1 2)", IType::Number(7), &env);
+        check("(/ 20 2 3)", IType::Number(3), &env);
+        check("(/ -7 2)", IType::Number(-3), &env);
+        check("(mod 7 3)", IType::Number(1), &env);
+        check("(mod -7 3)", IType::Number(2), &env);
+        check("(mod 7 -3)", IType::Number(-2), &env);
+        check("(+ 1 (* 2 (- 4 1)))", IType::Number(7), &env);
+        check("(< 1 2 3)", IType::True, &env);
+        check("(< 1 3 2)", IType::False, &env);
+        check("(>= 3 3 1)", IType::True, &env);
+        check("(= 2 2 2)", IType::True, &env);
+        check("(> 1)", IType::True, &env);
+        assert_eq!(
+            error_of("(/ 1 0)", &env),
+            (ErrorKind::Arithmetic, "division by zero".to_string())
+        );
+        assert_eq!(
+            error_of("(mod 1 0)", &env),
+            (ErrorKind::Arithmetic, "division by zero".to_string())
+        );
+        assert_eq!(
+            error_of("(+ 9223372036854775807 1)", &env),
+            (ErrorKind::Arithmetic, "integer overflow".to_string())
+        );
+        assert_eq!(
+            error_of("(- -9223372036854775808)", &env),
+            (ErrorKind::Arithmetic, "integer overflow".to_string())
+        );
+        assert_eq!(
+            error_of("(/ -9223372036854775808 -1)", &env),
+            (ErrorKind::Arithmetic, "integer overflow".to_string())
+        );
+        assert!(str_to_eval("(+ 1 :a)", &env).is_err());
+        assert!(str_to_eval("(-)", &env).is_err());
+        assert!(str_to_eval("(<)", &env).is_err());
+        assert_eq!(error_of("(-)", &env).0, ErrorKind::Arity);
+        assert_eq!(error_of("(mod 1 2 3)", &env).0, ErrorKind::Arity);
+        // the operators are values like any other function
+        check("((lambda (f) (f 1 2)) +)", IType::Number(3), &env);
+        check("((lambda (f) (f 1 2)) <)", IType::True, &env);
+        str_to_eval("(defun + (a b) 0)", &env).unwrap();
+        check("(+ 1 2)", IType::Number(0), &env);
+        check("((lambda (f) (f 1 2)) +)", IType::Number(0), &env);
+    }
+    #[test]
+    fn test_builtins() {
+        use env::Arity;
+        use std::cell::Cell;
+        let env = env::make_env();
+        let calls = Rc::new(Cell::new(0));
+        let seen = Rc::clone(&calls);
+        env::register_builtin(&env, "count-args", Arity::at_least(0), move |args| {
+            seen.set(seen.get() + 1);
+            Ok(Rc::new(IType::Number(args.len() as i64)))
+        });
+        env::register_builtin(&env, "second", Arity::exactly(2), |args| {
+            Ok(Rc::clone(&args[1]))
+        });
+        env::register_builtin(&env, "fail", Arity::between(0, 1), |_| {
+            Err("host said no".to_string())
+        });
+        env::register_builtin(&env, "even-only", Arity::exactly(1), |args| {
+            match *args[0] {
+                IType::Number(n) if n % 2 == 0 => Ok(Rc::clone(&args[0])),
+                _ => Err(format!("{} is not an even number", args[0])),
+            }
+        });
+        let counted = str_to_eval("(count-args 1 (+ 1 1) :c)", &env);
+        assert_eq!(*counted.unwrap(), IType::Number(3));
+        let second = str_to_eval("(second :a (list 1 2))", &env);
+        assert_eq!(second.unwrap().to_string(), "( 1 2)");
+        // builtins are values, so they can be passed to lambdas
+        let applied = str_to_eval("((lambda (f) (f 1 2)) second)", &env);
+        assert_eq!(*applied.unwrap(), IType::Number(2));
+        assert_eq!(calls.get(), 1);
+        assert_eq!(
+            error_of("(second 1)", &env),
+            (
+                ErrorKind::Arity,
+                "wrong number of arguments to second: expected 2, got 1".to_string()
+            )
+        );
+        assert_eq!(
+            error_of("(fail)", &env),
+            (ErrorKind::Builtin, "host said no".to_string())
+        );
+        assert!(str_to_eval("(fail 1 2)", &env).is_err());
+        assert_eq!(
+            error_of("(even-only (+ 1 2))", &env),
+            (ErrorKind::Builtin, "3 is not an even number".to_string())
+        );
+        assert_eq!(
+            str_to_eval("second", &env).unwrap().to_string(),
+            "builtin function second"
+        );
+        assert_eq!(Arity::between(1, 3).to_string(), "1 to 3");
+        assert_eq!(Arity::at_least(2).to_string(), "at least 2");
+        assert_eq!(Arity::exactly(0).accepts(1), false);
+    }
+    #[test]
+    fn test_scope_chain() {
+        let env = env::make_env();
+        // f refers to g before g exists, the global scope is shared, not copied
+        str_to_eval("(label f (lambda (x) (g x)))", &env).unwrap();
+        str_to_eval("(label g (lambda (x) (car x)))", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(f (list 1 2))", &env).unwrap(),
+            IType::Number(1)
+        );
+        str_to_eval(
+            "(label make-const (lambda (x) (lambda (y) (list x))))",
+            &env,
+        )
+        .unwrap();
+        str_to_eval("(label five (make-const 5))", &env).unwrap();
+        assert_eq!(str_to_eval("(five 6)", &env).unwrap().to_string(), "( 5)");
+        // a label inside a body binds in the call's scope only
+        str_to_eval("(defun keep (x) (label kept x))", &env).unwrap();
+        str_to_eval("(keep 1)", &env).unwrap();
+        assert_eq!(
+            error_of("kept", &env),
+            (ErrorKind::Unbound, "undefined variable kept".to_string())
+        );
+
+        let outer = env::Environment::new();
+        outer.insert("a".to_string(), Rc::new(IType::Number(1)));
+        let inner = env::Environment::extend(&outer);
+        assert_eq!(inner.get("a"), Some(Rc::new(IType::Number(1))));
+        inner.insert("a".to_string(), Rc::new(IType::Number(2)));
+        assert_eq!(inner.get("a"), Some(Rc::new(IType::Number(2))));
+        assert_eq!(outer.get("a"), Some(Rc::new(IType::Number(1))));
+        outer.insert("b".to_string(), Rc::new(IType::Nil));
+        assert!(inner.contains_key("b"));
+        assert!(!outer.contains_key("c"));
+    }
+    #[test]
+    fn test_call_frames() {
+        let env = env::make_env();
+        // the recursive call happens while the outer call still needs its own n
+        str_to_eval(
+            "(defun sum-to (n) (cond ((= n 0) 0) (else (+ n (sum-to (- n 1))))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(sum-to 50)", &env).unwrap(),
+            IType::Number(1275)
+        );
+        // an argument that itself calls the same function
+        str_to_eval("(defun twice (x) (* 2 x))", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(twice (twice (twice 1)))", &env).unwrap(),
+            IType::Number(8)
+        );
+        // arguments don't leak out of a call or into the next one
+        str_to_eval("(label make-adder (lambda (n) (lambda (x) (+ n x))))", &env).unwrap();
+        str_to_eval("(label add2 (make-adder 2))", &env).unwrap();
+        str_to_eval("(label add10 (make-adder 10))", &env).unwrap();
+        assert_eq!(*str_to_eval("(add2 1)", &env).unwrap(), IType::Number(3));
+        assert_eq!(*str_to_eval("(add10 1)", &env).unwrap(), IType::Number(11));
+        assert_eq!(
+            *str_to_eval("(add2 (add2 0))", &env).unwrap(),
+            IType::Number(4)
+        );
+        assert_eq!(
+            error_of("x", &env),
+            (ErrorKind::Unbound, "undefined variable x".to_string())
+        );
+        str_to_eval("(label peek (lambda (y) (list x)))", &env).unwrap();
+        assert_eq!(
+            error_of("(peek 1)", &env),
+            (ErrorKind::Unbound, "undefined variable x".to_string())
+        );
+
+        let add2 = env.get("add2").unwrap();
+        assert_eq!(
+            *env::apply(&add2, vec![Rc::new(IType::Number(5))]).unwrap(),
+            IType::Number(7)
+        );
+        assert!(env::apply(&add2, vec![]).is_err());
+        assert!(env::apply(&IType::Nil, vec![]).is_err());
+    }
 
+    #[test]
+    fn test_tail_calls() {
+        let env = env::make_env();
+        // deep enough to overflow the stack if every call took a rust frame
+        str_to_eval(
+            "(defun countdown (n) (cond ((= n 0) 0) (else (countdown (- n 1)))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(countdown 100000)", &env).unwrap(),
+            IType::Number(0)
+        );
+        // an accumulator carried through the tail call
+        str_to_eval(
+            "(defun sum-acc (n acc) (cond ((= n 0) acc) (else (sum-acc (- n 1) (+ acc n)))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(sum-acc 100000 0)", &env).unwrap(),
+            IType::Number(5000050000)
+        );
+        // mutual recursion is a tail call too
+        str_to_eval(
+            "(defun is-even (n) (cond ((= n 0) true) (else (is-odd (- n 1)))))",
+            &env,
+        )
+        .unwrap();
+        str_to_eval(
+            "(defun is-odd (n) (cond ((= n 0) false) (else (is-even (- n 1)))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(is-even 100001)", &env).unwrap(),
+            IType::False
+        );
+        assert_eq!(*str_to_eval("(is-odd 100001)", &env).unwrap(), IType::True);
+        // the caller's scope is back in place after a tail call returns
+        str_to_eval("(label n 7)", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(countdown 3)", &env).unwrap(),
+            IType::Number(0)
+        );
+        assert_eq!(*str_to_eval("n", &env).unwrap(), IType::Number(7));
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        // the default limit is sized for the stack of a main thread
+        let main_sized = thread::Builder::new().stack_size(8 * 1024 * 1024);
+        let run = main_sized.spawn(|| {
+            let env = env::make_env();
+            str_to_eval(
+                "(defun sum-to (n) (cond ((= n 0) 0) (else (+ n (sum-to (- n 1))))))",
+                &env,
+            )
+            .unwrap();
+            assert_eq!(
+                *str_to_eval("(sum-to 500)", &env).unwrap(),
+                IType::Number(125250)
+            );
+            // deep non-tail recursion stops with an error instead of overflowing
+            assert_eq!(
+                error_of("(sum-to 100000)", &env),
+                (
+                    ErrorKind::Depth,
+                    format!(
+                        "maximum recursion depth of {} exceeded",
+                        env::DEFAULT_MAX_DEPTH
+                    )
+                )
+            );
+            // and leaves the interpreter in a usable state
+            assert_eq!(
+                *str_to_eval("(sum-to 100)", &env).unwrap(),
+                IType::Number(5050)
+            );
+            // tail calls don't count towards the limit
+            str_to_eval(
+                "(defun countdown (n) (cond ((= n 0) 0) (else (countdown (- n 1)))))",
+                &env,
+            )
+            .unwrap();
+            assert_eq!(
+                *str_to_eval("(countdown 10000)", &env).unwrap(),
+                IType::Number(0)
+            );
+            assert_eq!(env::set_max_depth(20), env::DEFAULT_MAX_DEPTH);
+            assert!(str_to_eval("(sum-to 5)", &env).is_ok());
+            assert!(str_to_eval("(sum-to 50)", &env).is_err());
+            // deeply nested expressions are limited the same way
+            let nested = format!("{}0{}", "(+ 1 ".repeat(50), ")".repeat(50));
+            let tokens = tokenizer::parse_string(&nested).unwrap();
+            let exp = ast::stream_to_ast(&tokens).unwrap();
+            assert_eq!(env::eval(&env, &exp).unwrap_err().kind(), ErrorKind::Depth);
+        });
+        run.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let env = env::make_env();
+        str_to_eval("(defun f (x) (+ x y))", &env).unwrap();
+        str_to_eval("(defun g (x) (+ 1 (f x)))", &env).unwrap();
+        // the error knows what failed, where, and the calls on the way there
+        let e = str_to_eval("(g 1)", &env).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unbound);
+        assert_eq!(e.message(), "undefined variable y");
+        assert_eq!(e.expr(), Some(&ast::SExpType::Identifier("y".to_string())));
+        let frames: Vec<String> = e.backtrace().iter().map(|f| f.to_string()).collect();
+        assert_eq!(frames, vec!["(f 1)", "(g 1)"]);
+        // a tail call takes the place of its caller
+        str_to_eval("(defun h (x) (f x))", &env).unwrap();
+        let e = str_to_eval("(h 2)", &env).unwrap_err();
+        let frames: Vec<String> = e.backtrace().iter().map(|f| f.to_string()).collect();
+        assert_eq!(frames, vec!["(f 2)"]);
+
+        assert_eq!(
+            error_of("(f 1 2)", &env),
+            (
+                ErrorKind::Arity,
+                "wrong number of arguments to f: expected 1, got 2".to_string()
+            )
+        );
+        assert_eq!(
+            error_of("(car 5)", &env),
+            (ErrorKind::Type, "car expects a list, got 5".to_string())
+        );
+        assert_eq!(
+            error_of("(+ 1 \"a\")", &env),
+            (ErrorKind::Type, "+ expects numbers, got \"a\"".to_string())
+        );
+        assert_eq!(error_of("(quote)", &env).0, ErrorKind::Syntax);
+        assert_eq!(error_of("(1 2)", &env).0, ErrorKind::Type);
+        env::register_builtin(&env, "fail", env::Arity::exactly(0), |_| {
+            Err("host said no".to_string())
+        });
+        let e = str_to_eval("((lambda (n) (list (fail))) 3)", &env).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Builtin);
+        let frames: Vec<String> = e.backtrace().iter().map(|f| f.to_string()).collect();
+        assert_eq!(frames, vec!["(fail)", "(lambda 3)"]);
+
+        // the failing expression can be found again in the spanned form
+        let source = "(list 1 (car 5))";
+        let form = &ast::parse_spanned_program(source).unwrap()[0];
+        let e = env::eval(&env, &form.exp).unwrap_err();
+        let span = form.span_of(e.expr().unwrap()).unwrap();
+        assert_eq!(&source[span.start..span.end], "(car 5)");
+    }
+
+    #[test]
+    fn test_macros() {
+        let env = env::make_env();
+        // the arguments reach the expander unevaluated, so only one branch runs
+        str_to_eval(
+            "(defmacro my-if (c a b) (quasiquote (cond ((unquote c) (unquote a)) (else (unquote b)))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(my-if true 1 undefined-thing)", &env).unwrap(),
+            IType::Number(1)
+        );
+        assert_eq!(
+            *str_to_eval("(my-if nil undefined-thing 2)", &env).unwrap(),
+            IType::Number(2)
+        );
+        // the expansion runs in the caller's scope
+        str_to_eval("(defun pick (x) (my-if (= x 0) :zero :other))", &env).unwrap();
+        assert_eq!(str_to_eval("(pick 0)", &env).unwrap().to_string(), ":zero");
+        assert_eq!(str_to_eval("(pick 5)", &env).unwrap().to_string(), ":other");
+        // a macro can expand into another macro call
+        str_to_eval("(defmacro unless (c a b) (list (quote my-if) c b a))", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(unless false 1 2)", &env).unwrap(),
+            IType::Number(1)
+        );
+        // and build code out of its arguments
+        str_to_eval(
+            "(defmacro swap-args (call) (list (car call) (car (cdr (cdr call))) (car (cdr call))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(swap-args (- 1 10))", &env).unwrap(),
+            IType::Number(9)
+        );
+        // a tail call inside an expansion is still a tail call
+        str_to_eval(
+            "(defun countdown (n) (my-if (= n 0) 0 (countdown (- n 1))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(countdown 10000)", &env).unwrap(),
+            IType::Number(0)
+        );
+
+        assert_eq!(
+            str_to_eval("(macroexpand-1 (quote (unless a b c)))", &env)
+                .unwrap()
+                .to_string(),
+            "( my-if a c b)"
+        );
+        assert_eq!(
+            str_to_eval("(macroexpand (quote (unless a b c)))", &env)
+                .unwrap()
+                .to_string(),
+            "( cond ( a c) ( else b))"
+        );
+        // anything that isn't a macro call comes back as it was
+        assert_eq!(
+            str_to_eval("(macroexpand (quote (+ 1 2)))", &env)
+                .unwrap()
+                .to_string(),
+            "( + 1 2)"
+        );
+
+        assert_eq!(error_of("(defmacro cond (x) x)", &env).0, ErrorKind::Syntax);
+        assert_eq!(error_of("(my-if 1 2)", &env).0, ErrorKind::Arity);
+        str_to_eval("(defmacro bad (x) (list (lambda (y) (list y))))", &env).unwrap();
+        let e = str_to_eval("(bad 1)", &env).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Type);
+        let frames: Vec<String> = e.backtrace().iter().map(|f| f.to_string()).collect();
+        assert_eq!(frames, vec!["(bad 1)"]);
+    }
+
+    #[test]
+    fn test_syntax_rules() {
+        let env = env::make_env();
+        str_to_eval(
+            "(define-syntax my-list (syntax-rules () ((_ x ...) (list x ...))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            str_to_eval("(my-list 1 (+ 1 1) 3)", &env)
+                .unwrap()
+                .to_string(),
+            "( 1 2 3)"
+        );
+        str_to_eval(
+            "(define-syntax sum (syntax-rules () ((_ x ...) (+ 0 x ...))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(*str_to_eval("(sum)", &env).unwrap(), IType::Number(0));
+        assert_eq!(*str_to_eval("(sum 1 2 3)", &env).unwrap(), IType::Number(6));
+        // a repeated pattern can be a list, and rules are tried in order
+        str_to_eval(
+            "(define-syntax my-cond (syntax-rules (else) ((_ (else e)) e) ((_ (c e) ...) (cond (c e) ...))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(my-cond ((= 1 2) 1) ((= 1 1) 2))", &env).unwrap(),
+            IType::Number(2)
+        );
+        assert_eq!(
+            *str_to_eval("(my-cond (else 3))", &env).unwrap(),
+            IType::Number(3)
+        );
+        // a literal only matches itself
+        str_to_eval(
+            "(define-syntax for (syntax-rules (in) ((_ x in xs) (cons x xs)) ((_ x y) y)))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            str_to_eval("(for 1 in (list 2))", &env)
+                .unwrap()
+                .to_string(),
+            "( 1 2)"
+        );
+        assert_eq!(*str_to_eval("(for 1 5)", &env).unwrap(), IType::Number(5));
+
+        // the t bound by the template doesn't capture the caller's t
+        str_to_eval(
+            "(define-syntax my-or (syntax-rules () ((_ a b) ((lambda (t) (cond (t t) (else b))) a))))",
+            &env,
+        )
+        .unwrap();
+        str_to_eval("(label t 5)", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(my-or false t)", &env).unwrap(),
+            IType::Number(5)
+        );
+        assert_eq!(*str_to_eval("(my-or 7 t)", &env).unwrap(), IType::Number(7));
+        // nor does the + in the template see a + bound where the macro is used
+        str_to_eval(
+            "(define-syntax inc (syntax-rules () ((_ x) (+ x 1))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(let ((+ -)) (inc 5))", &env).unwrap(),
+            IType::Number(6)
+        );
+        assert_eq!(
+            str_to_eval("(macroexpand-1 (quote (my-list a b)))", &env)
+                .unwrap()
+                .to_string(),
+            "( list a b)"
+        );
+
+        assert_eq!(error_of("(for)", &env).0, ErrorKind::NoMatch);
+        assert_eq!(
+            error_of("(define-syntax f (syntax-rules () (_ x)))", &env).0,
+            ErrorKind::Syntax
+        );
+        assert_eq!(
+            error_of("(define-syntax f (syntax-rules () ((_ ... x) x)))", &env).0,
+            ErrorKind::Syntax
+        );
+        assert_eq!(
+            error_of("(define-syntax f (syntax-rules () (() 1)))", &env).0,
+            ErrorKind::Syntax
+        );
+        assert_eq!(
+            error_of(
+                "(define-syntax f (syntax-rules () ((_ (a a) ...) 1)))",
+                &env
+            )
+            .0,
+            ErrorKind::Syntax
+        );
+    }
+
+    #[test]
+    fn test_let_forms() {
+        let env = env::make_env();
+        str_to_eval("(label x 1)", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(let ((x 2) (y x)) (+ x y))", &env).unwrap(),
+            IType::Number(3)
+        );
+        assert_eq!(
+            *str_to_eval("(let* ((x 2) (y x)) (+ x y))", &env).unwrap(),
+            IType::Number(4)
+        );
+        assert_eq!(
+            *str_to_eval("(let* ((x 2) (x (* x 10))) x)", &env).unwrap(),
+            IType::Number(20)
+        );
+        assert_eq!(*str_to_eval("(let () x)", &env).unwrap(), IType::Number(1));
+        // the bindings don't outlive the form
+        assert_eq!(*str_to_eval("x", &env).unwrap(), IType::Number(1));
+        // a closure keeps the scope it was made in
+        str_to_eval("(label adder (let ((n 10)) (lambda (k) (+ n k))))", &env).unwrap();
+        assert_eq!(*str_to_eval("(adder 5)", &env).unwrap(), IType::Number(15));
+
+        // a letrec scope and the functions bound in it hold each other, but
+        // are freed once nothing else does
+        let made = str_to_eval("(letrec ((f (lambda () 1))) (lambda () (f)))", &env).unwrap();
+        let scope = match *made {
+            IType::Function(_, _, _, ref scope) => Rc::downgrade(scope),
+            _ => panic!("expected a function, got {}", made),
+        };
+        assert_eq!(Rc::strong_count(&scope.upgrade().unwrap()), 3);
+        drop(made);
+        assert!(scope.upgrade().is_none());
+        // and so are a call's scope and the functions defined in its body
+        str_to_eval(
+            "(defun outer () (defun helper () 1) (lambda () (helper)))",
+            &env,
+        )
+        .unwrap();
+        let made = str_to_eval("(outer)", &env).unwrap();
+        let scope = match *made {
+            IType::Function(_, _, _, ref scope) => Rc::downgrade(scope),
+            _ => panic!("expected a function, got {}", made),
+        };
+        assert_eq!(*str_to_eval("((outer))", &env).unwrap(), IType::Number(1));
+        drop(made);
+        assert!(scope.upgrade().is_none());
+
+        str_to_eval(
+            "(defun parity (n) (letrec ((ev (lambda (k) (cond ((= k 0) :even) (else (od (- k 1)))))) (od (lambda (k) (cond ((= k 0) :odd) (else (ev (- k 1))))))) (ev n)))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            str_to_eval("(parity 10)", &env).unwrap().to_string(),
+            ":even"
+        );
+        assert_eq!(str_to_eval("(parity 7)", &env).unwrap().to_string(), ":odd");
+        // the body is in tail position
+        str_to_eval(
+            "(defun count-down (n) (let ((m (- n 1))) (cond ((= n 0) 0) (else (count-down m)))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(count-down 10000)", &env).unwrap(),
+            IType::Number(0)
+        );
+
+        assert_eq!(error_of("(let ((x 1)))", &env).0, ErrorKind::Syntax);
+        assert_eq!(error_of("(let x x)", &env).0, ErrorKind::Syntax);
+        assert_eq!(error_of("(let ((x)) x)", &env).0, ErrorKind::Syntax);
+        assert_eq!(error_of("(let ((1 2)) 1)", &env).0, ErrorKind::Syntax);
+        assert_eq!(
+            error_of("(letrec ((a 1) (a 2)) a)", &env),
+            (
+                ErrorKind::Syntax,
+                "letrec binds a more than once".to_string()
+            )
+        );
+        assert_eq!(
+            error_of("(let ((y undefined-thing)) y)", &env).0,
+            ErrorKind::Unbound
+        );
+    }
+
+    #[test]
+    fn test_bodies_and_begin() {
+        let env = env::make_env();
+        // every form of a body runs, the last one is the value
+        str_to_eval("(defun remember (x) (defun last-arg () x) (* x 2))", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(remember 4)", &env).unwrap(),
+            IType::Number(8)
+        );
+        assert_eq!(*str_to_eval("(last-arg)", &env).unwrap(), IType::Number(4));
+        // a body can be any expression
+        assert_eq!(
+            *str_to_eval("((lambda (x) x) 7)", &env).unwrap(),
+            IType::Number(7)
+        );
+        assert_eq!(
+            *str_to_eval("((lambda () 3))", &env).unwrap(),
+            IType::Number(3)
+        );
+        assert_eq!(
+            *str_to_eval("(let ((y 1)) (label y 2) y)", &env).unwrap(),
+            IType::Number(2)
+        );
+
+        assert_eq!(
+            *str_to_eval("(begin (label z 5) (+ z 1))", &env).unwrap(),
+            IType::Number(6)
+        );
+        assert_eq!(*str_to_eval("(begin)", &env).unwrap(), IType::Nil);
+        // the last form of a begin is in tail position
+        str_to_eval(
+            "(defun loop-down (n) (begin (cond ((= n 0) 0) (else (loop-down (- n 1))))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(loop-down 10000)", &env).unwrap(),
+            IType::Number(0)
+        );
+        str_to_eval(
+            "(defun loop-body (n) n (cond ((= n 0) 0) (else (loop-body (- n 1)))))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(loop-body 10000)", &env).unwrap(),
+            IType::Number(0)
+        );
+
+        assert_eq!(error_of("(lambda (x))", &env).0, ErrorKind::Syntax);
+        assert_eq!(error_of("(defun f (x))", &env).0, ErrorKind::Syntax);
+        assert_eq!(
+            error_of("(begin 1 undefined-thing 2)", &env).0,
+            ErrorKind::Unbound
+        );
+    }
+
+    #[test]
+    fn test_lambda_lists() {
+        let env = env::make_env();
+        str_to_eval("(defun tail-of (a &rest more) more)", &env).unwrap();
+        assert_eq!(
+            str_to_eval("(tail-of 1 2 3)", &env).unwrap().to_string(),
+            "( 2 3)"
+        );
+        assert_eq!(*str_to_eval("(tail-of 1)", &env).unwrap(), IType::Nil);
+        assert_eq!(
+            str_to_eval("((lambda (a . more) (cons a more)) 1 2)", &env)
+                .unwrap()
+                .to_string(),
+            "( 1 2)"
+        );
+        // a default can use the parameters before it
+        str_to_eval(
+            "(defun scale (x &optional (by 2) unit) (list (* x by) unit))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            str_to_eval("(scale 3)", &env).unwrap().to_string(),
+            "( 6 Nil)"
+        );
+        assert_eq!(
+            str_to_eval("(scale 3 10 :cm)", &env).unwrap().to_string(),
+            "( 30 :cm)"
+        );
+        str_to_eval("(defun twice (x &optional (y x)) (+ x y))", &env).unwrap();
+        assert_eq!(*str_to_eval("(twice 4)", &env).unwrap(), IType::Number(8));
+
+        str_to_eval(
+            "(defun box (&key (width 1) height) (list width height))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(str_to_eval("(box)", &env).unwrap().to_string(), "( 1 Nil)");
+        assert_eq!(
+            str_to_eval("(box :height 5 :width 2)", &env)
+                .unwrap()
+                .to_string(),
+            "( 2 5)"
+        );
+        // the first of repeated keywords wins
+        assert_eq!(
+            str_to_eval("(box :width 2 :height 3 :width 4)", &env)
+                .unwrap()
+                .to_string(),
+            "( 2 3)"
+        );
+        str_to_eval("(defun opts (&rest all &key v) (list v all))", &env).unwrap();
+        assert_eq!(
+            str_to_eval("(opts :v 1)", &env).unwrap().to_string(),
+            "( 1 ( :v 1))"
+        );
+
+        assert_eq!(
+            error_of("(scale)", &env),
+            (
+                ErrorKind::Arity,
+                "wrong number of arguments to scale: expected 1 to 3, got 0".to_string()
+            )
+        );
+        assert_eq!(
+            error_of("((lambda (a &rest b) a))", &env),
+            (
+                ErrorKind::Arity,
+                "wrong number of arguments to lambda: expected at least 1, got 0".to_string()
+            )
+        );
+        assert_eq!(error_of("(box :width)", &env).0, ErrorKind::Arity);
+        assert_eq!(error_of("(box :depth 1)", &env).0, ErrorKind::Arity);
+        assert_eq!(error_of("(box 1 2)", &env).0, ErrorKind::Type);
+        assert_eq!(error_of("(lambda (a &rest) a)", &env).0, ErrorKind::Syntax);
+        assert_eq!(
+            error_of("(lambda (a &rest b c) a)", &env).0,
+            ErrorKind::Syntax
+        );
+        assert_eq!(
+            error_of("(lambda (&key a &optional b) a)", &env).0,
+            ErrorKind::Syntax
+        );
+        assert_eq!(error_of("(lambda (a a) a)", &env).0, ErrorKind::Syntax);
+        assert_eq!(error_of("(lambda ((a 1)) a)", &env).0, ErrorKind::Syntax);
+    }
+
+    #[test]
+    fn test_set() {
+        let env = env::make_env();
+        str_to_eval("(label total 0)", &env).unwrap();
+        str_to_eval("(defun add-to-total (x) (set! total (+ total x)))", &env).unwrap();
+        str_to_eval("(add-to-total 3)", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(add-to-total 4)", &env).unwrap(),
+            IType::Number(7)
+        );
+        assert_eq!(*str_to_eval("total", &env).unwrap(), IType::Number(7));
+        // a counter keeps its state between calls, and each has its own
+        str_to_eval(
+            "(defun make-counter () (let ((n 0)) (lambda () (set! n (+ n 1)) n)))",
+            &env,
+        )
+        .unwrap();
+        str_to_eval("(label c1 (make-counter))", &env).unwrap();
+        str_to_eval("(label c2 (make-counter))", &env).unwrap();
+        str_to_eval("(c1)", &env).unwrap();
+        str_to_eval("(c1)", &env).unwrap();
+        assert_eq!(*str_to_eval("(c1)", &env).unwrap(), IType::Number(3));
+        assert_eq!(*str_to_eval("(c2)", &env).unwrap(), IType::Number(1));
+        // the nearest binding changes, not the one it shadows
+        assert_eq!(
+            *str_to_eval("(let ((total 1)) (set! total 10) total)", &env).unwrap(),
+            IType::Number(10)
+        );
+        assert_eq!(*str_to_eval("total", &env).unwrap(), IType::Number(7));
+
+        assert_eq!(
+            error_of("(set! nowhere 1)", &env),
+            (
+                ErrorKind::Unbound,
+                "cannot set! undefined variable nowhere".to_string()
+            )
+        );
+        assert!(!env.contains_key("nowhere"));
+        assert_eq!(error_of("(set! total)", &env).0, ErrorKind::Syntax);
+        assert_eq!(error_of("(set! 1 2)", &env).0, ErrorKind::Syntax);
+    }
+
+    #[test]
+    fn test_pairs() {
+        let env = env::make_env();
+        assert_eq!(
+            str_to_eval("(cons 1 2)", &env).unwrap().to_string(),
+            "( 1 . 2)"
+        );
+        assert_eq!(
+            str_to_eval("(cons 1 (cons 2 3))", &env)
+                .unwrap()
+                .to_string(),
+            "( 1 2 . 3)"
+        );
+        assert_eq!(
+            *str_to_eval("(cdr (cons 1 2))", &env).unwrap(),
+            IType::Number(2)
+        );
+        assert_eq!(
+            *str_to_eval("(equal (quote (1 . 2)) (cons 1 2))", &env).unwrap(),
+            IType::True
+        );
+        assert_eq!(
+            *str_to_eval("(equal (cons 1 2) (list 1 2))", &env).unwrap(),
+            IType::False
+        );
+        // lists that share a tail see changes made to it
+        str_to_eval("(label tail (list 2 3))", &env).unwrap();
+        str_to_eval("(label xs (cons 1 tail))", &env).unwrap();
+        str_to_eval("(label ys (cons 0 tail))", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(set-car! tail 20)", &env).unwrap(),
+            IType::Nil
+        );
+        assert_eq!(str_to_eval("xs", &env).unwrap().to_string(), "( 1 20 3)");
+        assert_eq!(str_to_eval("ys", &env).unwrap().to_string(), "( 0 20 3)");
+        str_to_eval("(set-cdr! (cdr tail) 4)", &env).unwrap();
+        assert_eq!(
+            str_to_eval("xs", &env).unwrap().to_string(),
+            "( 1 20 3 . 4)"
+        );
+        assert_eq!(
+            *str_to_eval("(eq (cdr xs) (cdr ys))", &env).unwrap(),
+            IType::True
+        );
+
+        // building and walking a long list is linear, and dropping it
+        // doesn't recurse
+        str_to_eval(
+            "(defun build (n acc) (cond ((= n 0) acc) (else (build (- n 1) (cons n acc)))))",
+            &env,
+        )
+        .unwrap();
+        str_to_eval(
+            "(defun walk (l n) (cond ((eq l nil) n) (else (walk (cdr l) (+ n 1)))))",
+            &env,
+        )
+        .unwrap();
+        str_to_eval("(label big (build 100000 ()))", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(walk big 0)", &env).unwrap(),
+            IType::Number(100000)
+        );
+        str_to_eval("(label big nil)", &env).unwrap();
+
+        // so are lists nested deep inside each other
+        str_to_eval(
+            "(defun nest (n acc) (cond ((= n 0) acc) (else (nest (- n 1) (list acc)))))",
+            &env,
+        )
+        .unwrap();
+        str_to_eval("(label deep (nest 100000 nil))", &env).unwrap();
+        assert!(str_to_eval("deep", &env)
+            .unwrap()
+            .to_string()
+            .starts_with("( ( ( "));
+        assert_eq!(
+            *str_to_eval("(equal deep (nest 100000 nil))", &env).unwrap(),
+            IType::True
+        );
+        assert_eq!(error_of("(macroexpand deep)", &env).0, ErrorKind::Depth);
+        str_to_eval("(label deep nil)", &env).unwrap();
+
+        // lists that loop are written with ... where they would repeat
+        str_to_eval("(label ring (list 1 2))", &env).unwrap();
+        str_to_eval("(set-cdr! (cdr ring) ring)", &env).unwrap();
+        assert_eq!(
+            str_to_eval("ring", &env).unwrap().to_string(),
+            "( 1 2 . ...)"
+        );
+        str_to_eval("(label lasso (list 0 1 2))", &env).unwrap();
+        str_to_eval("(set-cdr! (cdr (cdr lasso)) (cdr lasso))", &env).unwrap();
+        assert_eq!(
+            str_to_eval("lasso", &env).unwrap().to_string(),
+            "( 0 1 2 . ...)"
+        );
+        str_to_eval("(label inner (list 1 2))", &env).unwrap();
+        str_to_eval("(set-car! (cdr inner) inner)", &env).unwrap();
+        assert_eq!(str_to_eval("inner", &env).unwrap().to_string(), "( 1 ...)");
+        str_to_eval("(label other (list 1 2 1 2))", &env).unwrap();
+        str_to_eval("(set-cdr! (cdr (cdr (cdr other))) other)", &env).unwrap();
+        assert_eq!(
+            *str_to_eval("(equal ring other)", &env).unwrap(),
+            IType::True
+        );
+        assert_eq!(
+            *str_to_eval("(equal ring (list 1 2))", &env).unwrap(),
+            IType::False
+        );
+        assert_eq!(error_of("(macroexpand ring)", &env).0, ErrorKind::Type);
+        // and == and {:?} stop where they loop too
+        let ring = str_to_eval("ring", &env).unwrap();
+        assert_eq!(*ring, *str_to_eval("other", &env).unwrap());
+        assert_eq!(format!("{:?}", ring), "Pair(( 1 2 . ...))");
+        let err = str_to_eval("(+ ring 1)", &env).unwrap_err();
+        assert_eq!(err, err.clone());
+
+        assert_eq!(error_of("(set-car! nil 1)", &env).0, ErrorKind::Type);
+        assert_eq!(error_of("(set-cdr! 5 1)", &env).0, ErrorKind::Type);
+        assert_eq!(error_of("(cdr 5)", &env).0, ErrorKind::Type);
+    }
+
+    #[test]
+    fn test_quoted_data() {
+        let env = env::make_env();
+        assert_eq!(
+            str_to_eval("'(a b c)", &env).unwrap().to_string(),
+            "( a b c)"
+        );
+        assert_eq!(str_to_eval("'x", &env).unwrap().to_string(), "x");
+        assert_eq!(*str_to_eval("'5", &env).unwrap(), IType::Number(5));
+        assert_eq!(
+            str_to_eval("(car '(a b c))", &env).unwrap().to_string(),
+            "a"
+        );
+        assert_eq!(
+            str_to_eval("(cdr '(a b c))", &env).unwrap().to_string(),
+            "( b c)"
+        );
+        assert_eq!(
+            str_to_eval("(cons 'z '(a b))", &env).unwrap().to_string(),
+            "( z a b)"
+        );
+        assert_eq!(
+            str_to_eval("'(1 (2 \"s\") . 3)", &env).unwrap().to_string(),
+            "( 1 ( 2 \"s\") . 3)"
+        );
+        assert_eq!(
+            *str_to_eval("(equal '(a (b)) (list 'a (list 'b)))", &env).unwrap(),
+            IType::True
+        );
+        // nil, true and false are the constants in quoted data as well
+        assert_eq!(*str_to_eval("'nil", &env).unwrap(), IType::Nil);
+        assert_eq!(*str_to_eval("(equal 'nil nil)", &env).unwrap(), IType::True);
+        assert_eq!(
+            *str_to_eval("(cond ((car '(nil)) 1) (else 2))", &env).unwrap(),
+            IType::Number(2)
+        );
+        assert_eq!(
+            *str_to_eval("(cdr '(false true))", &env).unwrap(),
+            *env::list_from(vec![Rc::new(IType::True)])
+        );
+        // quoted data can be taken apart and run as code
+        str_to_eval(
+            "(defmacro run-reversed (args) (cons (car (cdr args)) (cons (car args) nil)))",
+            &env,
+        )
+        .unwrap();
+        assert_eq!(
+            *str_to_eval("(run-reversed (5 -))", &env).unwrap(),
+            IType::Number(-5)
+        );
+        // and changed, without touching the code it was quoted from
+        str_to_eval("(defun fresh () '(1 2))", &env).unwrap();
+        str_to_eval("(set-car! (fresh) 10)", &env).unwrap();
+        assert_eq!(str_to_eval("(fresh)", &env).unwrap().to_string(), "( 1 2)");
+    }
 }
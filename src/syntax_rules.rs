@@ -0,0 +1,355 @@
+use ast::SExpType;
+use env::{ErrorKind, EvalError};
+use std::cell::Cell;
+use std::collections::HashMap;
+
+/// A macro written with `syntax-rules`: a list of patterns, each with the
+/// template that a use of the macro matching it expands into.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxRules {
+    literals: Vec<String>,
+    rules: Vec<(SExpType, SExpType)>,
+}
+
+// what a pattern variable matched: one form, or one binding for every
+// repetition of the `...` it sits under
+#[derive(Debug, Clone)]
+enum Binding {
+    One(SExpType),
+    Many(Vec<Binding>),
+}
+
+thread_local! {
+    static NEXT_MARK: Cell<usize> = Cell::new(1);
+}
+
+// a mark no expansion on this thread has used yet
+fn fresh_mark() -> usize {
+    NEXT_MARK.with(|next| {
+        let mark = next.get();
+        next.set(mark + 1);
+        mark
+    })
+}
+
+fn is_ellipsis(exp: &SExpType) -> bool {
+    exp.get_identifier_name()
+        .map_or(false, |name| name == "...")
+}
+
+fn bad_rules<T, S: Into<String>>(message: S) -> Result<T, EvalError> {
+    Err(EvalError::new(ErrorKind::Syntax, message))
+}
+
+impl SyntaxRules {
+    /// Reads `(syntax-rules (literal ...) (pattern template) ...)`. Each
+    /// pattern is a list whose first item, the macro's own name, is ignored.
+    pub fn parse(spec: &SExpType) -> Result<SyntaxRules, EvalError> {
+        let items = match spec.get_exp() {
+            Some(items)
+                if items.len() >= 2
+                    && items[0].get_identifier_name() == Some("syntax-rules".to_string()) =>
+            {
+                items
+            }
+            _ => {
+                return bad_rules(format!(
+                    "expected (syntax-rules (literals) rules...), got {}",
+                    spec
+                ))
+            }
+        };
+        let mut literals = Vec::new();
+        match items[1].get_exp() {
+            Some(names) => {
+                for name in names {
+                    match name.get_identifier_name() {
+                        Some(name) => literals.push(name),
+                        None => {
+                            return bad_rules(format!(
+                                "syntax-rules literal {} is not an identifier",
+                                name
+                            ))
+                        }
+                    }
+                }
+            }
+            None => {
+                return bad_rules(format!(
+                    "syntax-rules literals must be a list, got {}",
+                    items[1]
+                ))
+            }
+        }
+        let mut syntax = SyntaxRules {
+            literals: literals,
+            rules: Vec::new(),
+        };
+        for rule in &items[2..] {
+            match rule.get_exp() {
+                Some(pair)
+                    if pair.len() == 2 && pair[0].get_exp().map_or(false, |p| !p.is_empty()) =>
+                {
+                    let patterns = &pair[0].get_exp().unwrap()[1..];
+                    check_ellipses(patterns, &pair[0])?;
+                    let mut names = Vec::new();
+                    for name in patterns.iter().flat_map(|p| syntax.pattern_variables(p)) {
+                        if names.contains(&name) {
+                            return bad_rules(format!(
+                                "pattern variable {} appears more than once in {}",
+                                name, pair[0]
+                            ));
+                        }
+                        names.push(name);
+                    }
+                    syntax.rules.push((pair[0].clone(), pair[1].clone()));
+                }
+                _ => {
+                    return bad_rules(format!(
+                        "syntax-rules rule should be of the form ((_ pattern...) template), got {}",
+                        rule
+                    ))
+                }
+            }
+        }
+        Ok(syntax)
+    }
+
+    /// Expands `form`, a use of this macro, with the first rule whose
+    /// pattern matches it. Identifiers the template brings in are renamed
+    /// for this expansion alone, those that came from `form` are left as
+    /// they were.
+    pub fn expand(&self, form: &SExpType) -> Result<SExpType, EvalError> {
+        let args = match form.get_exp() {
+            Some(items) if !items.is_empty() => &items[1..],
+            _ => return bad_rules(format!("{} is not a macro call", form)),
+        };
+        for &(ref pattern, ref template) in &self.rules {
+            let mut bindings = HashMap::new();
+            if self.match_list(&pattern.get_exp().unwrap()[1..], args, &mut bindings)? {
+                return instantiate(template, &bindings, fresh_mark());
+            }
+        }
+        Err(EvalError::new(
+            ErrorKind::NoMatch,
+            format!("no syntax rule matches {}", form),
+        ))
+    }
+
+    // matches one pattern against one form, adding what its variables
+    // matched to `bindings`
+    fn match_pattern(
+        &self,
+        pattern: &SExpType,
+        form: &SExpType,
+        bindings: &mut HashMap<String, Binding>,
+    ) -> Result<bool, EvalError> {
+        match *pattern {
+            SExpType::Identifier(ref name) | SExpType::Renamed(ref name, _) => {
+                if self.literals.contains(name) {
+                    Ok(form.get_identifier_name().as_ref() == Some(name))
+                } else {
+                    if name != "_" {
+                        bindings
+                            .insert(pattern.binding_name().unwrap(), Binding::One(form.clone()));
+                    }
+                    Ok(true)
+                }
+            }
+            SExpType::Exp(ref patterns) => match form.get_exp() {
+                Some(forms) => self.match_list(patterns, forms, bindings),
+                None => Ok(false),
+            },
+            _ => Ok(pattern == form),
+        }
+    }
+
+    // matches the items of a list pattern, where one item may be followed by
+    // `...` and then matches any number of forms
+    fn match_list(
+        &self,
+        patterns: &[SExpType],
+        forms: &[SExpType],
+        bindings: &mut HashMap<String, Binding>,
+    ) -> Result<bool, EvalError> {
+        let ellipsis = match patterns.iter().position(is_ellipsis) {
+            Some(index) => index,
+            None => {
+                if patterns.len() != forms.len() {
+                    return Ok(false);
+                }
+                for (pattern, form) in patterns.iter().zip(forms) {
+                    if !self.match_pattern(pattern, form, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                return Ok(true);
+            }
+        };
+        let before = &patterns[..ellipsis - 1];
+        let repeated = &patterns[ellipsis - 1];
+        let after = &patterns[ellipsis + 1..];
+        if forms.len() < before.len() + after.len() {
+            return Ok(false);
+        }
+        let repeat_end = forms.len() - after.len();
+        if !self.match_list(before, &forms[..before.len()], bindings)?
+            || !self.match_list(after, &forms[repeat_end..], bindings)?
+        {
+            return Ok(false);
+        }
+        let mut repetitions = Vec::new();
+        for form in &forms[before.len()..repeat_end] {
+            let mut repetition = HashMap::new();
+            if !self.match_pattern(repeated, form, &mut repetition)? {
+                return Ok(false);
+            }
+            repetitions.push(repetition);
+        }
+        for name in self.pattern_variables(repeated) {
+            let mut matched = Vec::with_capacity(repetitions.len());
+            for repetition in &mut repetitions {
+                match repetition.remove(&name) {
+                    Some(binding) => matched.push(binding),
+                    None => {
+                        return bad_rules(format!(
+                            "pattern variable {} matched more than once in {}",
+                            name, repeated
+                        ))
+                    }
+                }
+            }
+            bindings.insert(name, Binding::Many(matched));
+        }
+        Ok(true)
+    }
+
+    fn pattern_variables(&self, pattern: &SExpType) -> Vec<String> {
+        match *pattern {
+            SExpType::Identifier(ref name) | SExpType::Renamed(ref name, _) => {
+                if self.literals.contains(name) || name == "_" || name == "..." {
+                    vec![]
+                } else {
+                    vec![pattern.binding_name().unwrap()]
+                }
+            }
+            SExpType::Exp(ref items) => items
+                .iter()
+                .flat_map(|item| self.pattern_variables(item))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+// the items of a list pattern may include one `...`, following the item it
+// repeats
+fn check_ellipses(items: &[SExpType], pattern: &SExpType) -> Result<(), EvalError> {
+    let ellipses = items.iter().filter(|item| is_ellipsis(item)).count();
+    if ellipses > 1 || items.first().map_or(false, is_ellipsis) {
+        return bad_rules(format!("misplaced ... in pattern {}", pattern));
+    }
+    for item in items {
+        if let Some(sub_items) = item.get_exp() {
+            check_ellipses(sub_items, pattern)?;
+        }
+    }
+    Ok(())
+}
+
+// the pattern variables in `template` that are bound to repetitions
+fn repeated_variables(
+    template: &SExpType,
+    bindings: &HashMap<String, Binding>,
+    names: &mut Vec<String>,
+) {
+    match *template {
+        SExpType::Identifier(_) | SExpType::Renamed(..) => {
+            let name = template.binding_name().unwrap();
+            if let Some(&Binding::Many(_)) = bindings.get(&name) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        SExpType::Exp(ref items) => {
+            for item in items {
+                repeated_variables(item, bindings, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+// fills the pattern variables in `template` in with what they matched and
+// renames every other identifier with `mark`
+fn instantiate(
+    template: &SExpType,
+    bindings: &HashMap<String, Binding>,
+    mark: usize,
+) -> Result<SExpType, EvalError> {
+    match *template {
+        SExpType::Identifier(ref name) | SExpType::Renamed(ref name, _) => {
+            match bindings.get(&template.binding_name().unwrap()) {
+                Some(&Binding::One(ref form)) => Ok(form.clone()),
+                Some(&Binding::Many(_)) => {
+                    bad_rules(format!("pattern variable {} needs a ... after it", name))
+                }
+                None => Ok(SExpType::Renamed(name.clone(), mark)),
+            }
+        }
+        SExpType::Exp(ref items) => {
+            let mut expanded = Vec::with_capacity(items.len());
+            let mut i = 0;
+            while i < items.len() {
+                if i + 1 < items.len() && is_ellipsis(&items[i + 1]) {
+                    expanded.extend(instantiate_repeated(&items[i], bindings, mark)?);
+                    i += 2;
+                } else {
+                    expanded.push(instantiate(&items[i], bindings, mark)?);
+                    i += 1;
+                }
+            }
+            Ok(SExpType::Exp(expanded))
+        }
+        _ => Ok(template.clone()),
+    }
+}
+
+// expands a template item that is followed by `...` once for every
+// repetition its pattern variables matched
+fn instantiate_repeated(
+    template: &SExpType,
+    bindings: &HashMap<String, Binding>,
+    mark: usize,
+) -> Result<Vec<SExpType>, EvalError> {
+    let mut names = Vec::new();
+    repeated_variables(template, bindings, &mut names);
+    let count = match names.first().and_then(|name| bindings.get(name)) {
+        Some(&Binding::Many(ref matched)) => matched.len(),
+        _ => {
+            return bad_rules(format!(
+                "{} is followed by ... but has no repeated pattern variable",
+                template
+            ))
+        }
+    };
+    let mut expanded = Vec::with_capacity(count);
+    for k in 0..count {
+        let mut repetition = bindings.clone();
+        for name in &names {
+            let matched = match bindings[name] {
+                Binding::Many(ref matched) if matched.len() == count => matched[k].clone(),
+                _ => {
+                    return bad_rules(format!(
+                        "pattern variables in {} repeat a different number of times",
+                        template
+                    ))
+                }
+            };
+            repetition.insert(name.clone(), matched);
+        }
+        expanded.push(instantiate(template, &repetition, mark)?);
+    }
+    Ok(expanded)
+}
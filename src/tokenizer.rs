@@ -1,38 +1,272 @@
+use ast::ParseError;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum TokenType {
     o_brace,
     c_brace,
     Identifier(String),
+    Str(String),
+    /// `#;`, the parser drops the form that follows it
+    DatumComment,
+    /// `'`, `` ` ``, `,` and `,@`, the parser wraps the form that follows
+    /// them in `quote`, `quasiquote`, `unquote` and `unquote-splicing`
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+}
+
+/// Where a token sits in the source. `start` and `end` are byte offsets,
+/// `line` and `column` are 1-based and locate `start`. A default span
+/// (line 0) means the location is unknown.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// A span running from the start of `self` to the end of `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.line != 0
+    }
+}
+
+// walks the input one char at a time, keeping track of line and column
+struct Cursor {
+    chars: Vec<(usize, char)>,
+    len: usize,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Cursor {
+    fn new(input: &str) -> Cursor {
+        Cursor {
+            chars: input.char_indices().collect(),
+            len: input.len(),
+            index: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.chars.get(self.index + n).map(|&(_, c)| c)
+    }
+
+    // the span of the char under the cursor, empty at the end of input
+    fn here(&self) -> Span {
+        let (start, end) = match self.chars.get(self.index) {
+            Some(&(offset, c)) => (offset, offset + c.len_utf8()),
+            None => (self.len, self.len),
+        };
+        Span {
+            start: start,
+            end: end,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+}
+
+pub fn parse_string(input: &String) -> Result<Vec<TokenType>, ParseError> {
+    tokenize(input).map(|(tokens, _)| tokens)
 }
 
-pub fn parse_string(input: &String) -> Vec<TokenType> {
+/// Tokenizes `input` and records a span for every token. The spans are
+/// index for index with the tokens.
+pub fn tokenize(input: &str) -> Result<(Vec<TokenType>, Vec<Span>), ParseError> {
     let mut tokens = Vec::new();
+    let mut spans = Vec::new();
     let mut current_identifier = String::new();
-    for c in input.chars() {
-        if c == '(' {
-            if current_identifier.len() != 0 {
-                tokens.push(TokenType::Identifier(current_identifier.clone()));
-                current_identifier.clear();
+    let mut identifier_span = Span::default();
+    let mut cursor = Cursor::new(input);
+    while let Some(c) = cursor.peek() {
+        let here = cursor.here();
+        if c == '#' && current_identifier.len() == 0 {
+            match cursor.peek_nth(1) {
+                Some('|') => {
+                    skip_block_comment(&mut cursor, tokens.len())?;
+                    continue;
+                }
+                Some(';') => {
+                    let mut comment_span = here;
+                    comment_span.end = here.start + 2;
+                    cursor.bump();
+                    cursor.bump();
+                    tokens.push(TokenType::DatumComment);
+                    spans.push(comment_span);
+                    continue;
+                }
+                _ => {}
             }
-            tokens.push(TokenType::o_brace);
-        } else if c == ')' {
+        }
+        let is_prefix = c == '\'' || c == '`' || c == ',';
+        if c == '(' || c == ')' || c == '"' || c == ';' || is_prefix || c.is_whitespace() {
             if current_identifier.len() != 0 {
                 tokens.push(TokenType::Identifier(current_identifier.clone()));
+                spans.push(identifier_span);
                 current_identifier.clear();
             }
-            tokens.push(TokenType::c_brace);
-        } else if c.is_whitespace() {
-            if current_identifier.len() != 0 {
-                tokens.push(TokenType::Identifier(current_identifier.clone()));
-                current_identifier.clear();
+            if c == '"' {
+                let literal = read_string(&mut cursor, tokens.len())?;
+                let mut literal_span = here;
+                literal_span.end = cursor.here().start;
+                tokens.push(TokenType::Str(literal));
+                spans.push(literal_span);
+                continue;
+            } else if c == ';' {
+                // a line comment runs up to, but not including, the newline
+                while cursor.peek().map_or(false, |c| c != '\n') {
+                    cursor.bump();
+                }
+                continue;
+            } else if c == ',' && cursor.peek_nth(1) == Some('@') {
+                let mut splice_span = here;
+                splice_span.end = here.start + 2;
+                cursor.bump();
+                cursor.bump();
+                tokens.push(TokenType::UnquoteSplicing);
+                spans.push(splice_span);
+                continue;
+            } else if is_prefix {
+                tokens.push(match c {
+                    '\'' => TokenType::Quote,
+                    '`' => TokenType::Quasiquote,
+                    _ => TokenType::Unquote,
+                });
+                spans.push(here);
+            } else if c == '(' {
+                tokens.push(TokenType::o_brace);
+                spans.push(here);
+            } else if c == ')' {
+                tokens.push(TokenType::c_brace);
+                spans.push(here);
             }
         } else {
+            if current_identifier.len() == 0 {
+                identifier_span = here;
+            }
+            identifier_span.end = here.end;
             current_identifier.push(c);
         }
+        cursor.bump();
     }
     if current_identifier.len() != 0 {
         tokens.push(TokenType::Identifier(current_identifier.clone()));
+        spans.push(identifier_span);
         current_identifier.clear();
     }
-    tokens
+    Ok((tokens, spans))
+}
+
+// skips a `#| ... |#` comment, which may contain other block comments,
+// with the cursor on the opening `#`
+fn skip_block_comment(cursor: &mut Cursor, index: usize) -> Result<(), ParseError> {
+    let open = cursor.here();
+    let mut depth = 0;
+    loop {
+        match (cursor.peek(), cursor.peek_nth(1)) {
+            (Some('#'), Some('|')) => {
+                depth += 1;
+                cursor.bump();
+                cursor.bump();
+            }
+            (Some('|'), Some('#')) => {
+                depth -= 1;
+                cursor.bump();
+                cursor.bump();
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            (Some(_), _) => {
+                cursor.bump();
+            }
+            (None, _) => {
+                return Err(ParseError::UnterminatedComment {
+                    index: index,
+                    span: open,
+                })
+            }
+        }
+    }
+}
+
+// reads a double quoted literal with the cursor on the opening quote and
+// leaves the cursor just past the closing quote
+fn read_string(cursor: &mut Cursor, index: usize) -> Result<String, ParseError> {
+    let open = cursor.here();
+    cursor.bump();
+    let mut literal = String::new();
+    loop {
+        let escape_span = cursor.here();
+        match cursor.bump() {
+            None => {
+                return Err(ParseError::UnterminatedString {
+                    index: index,
+                    span: open,
+                })
+            }
+            Some('"') => return Ok(literal),
+            Some('\\') => {
+                let invalid = || ParseError::InvalidEscape {
+                    index: index,
+                    span: escape_span,
+                };
+                match cursor.bump() {
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some('"') => literal.push('"'),
+                    Some('\\') => literal.push('\\'),
+                    Some('u') => {
+                        if cursor.bump() != Some('{') {
+                            return Err(invalid());
+                        }
+                        let mut hex = String::new();
+                        loop {
+                            match cursor.bump() {
+                                Some('}') => break,
+                                Some(h) if h.is_digit(16) && hex.len() < 6 => hex.push(h),
+                                _ => return Err(invalid()),
+                            }
+                        }
+                        let code = u32::from_str_radix(&hex, 16).map_err(|_| invalid())?;
+                        literal.push(::std::char::from_u32(code).ok_or_else(invalid)?);
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            Some(c) => literal.push(c),
+        }
+    }
 }